@@ -7,13 +7,13 @@ use modular_core::{
     types::Param,
     uuid::Uuid,
 };
-use modular_server::spawn;
+use modular_server::{spawn, ServerConfig};
 
 fn main() -> anyhow::Result<()> {
     // let matches = get_matches();
 
-    let (_modular_handle, _receiving_server_handle, _sending_server_handle) =
-        spawn("127.0.0.1:7813".to_owned(), "7812".to_owned());
+    let (_modular_handle, _receiving_server_handle, _sending_server_handle, _incoming_tx) =
+        spawn(ServerConfig::default());
 
     let (incoming_tx, incoming_rx) = mpsc::channel();
     let (outgoing_tx, outgoing_rx) = mpsc::channel();
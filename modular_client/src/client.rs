@@ -7,6 +7,7 @@ use std::{
 };
 
 use modular_core::message::InputMessage;
+use modular_server::MAX_PACKET_SIZE;
 use rosc::encoder;
 
 use crate::osc::{message_to_osc, osc_to_message, Message};
@@ -30,7 +31,7 @@ pub fn start_recieving_client(host_address: String, tx: Sender<Message>) {
     let sock = UdpSocket::bind(addr).unwrap();
     println!("Listening to {}", addr);
 
-    let mut buf = [0u8; rosc::decoder::MTU];
+    let mut buf = [0u8; MAX_PACKET_SIZE];
 
     loop {
         match sock.recv_from(&mut buf) {
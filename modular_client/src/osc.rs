@@ -5,8 +5,15 @@ use modular_core::{
     types::Param,
     uuid::Uuid,
 };
-use rosc::OscType::{Float as OscFloat, Int as OscInt, String as OscStr};
-use rosc::{OscMessage, OscPacket, OscType};
+use rosc::OscType::{Float as OscFloat, Int as OscInt, Long as OscLong, String as OscStr};
+use rosc::{OscBundle, OscMessage, OscPacket, OscType};
+
+fn bndl(content: Vec<OscPacket>) -> OscPacket {
+    OscPacket::Bundle(OscBundle {
+        content,
+        timetag: (0, 1),
+    })
+}
 
 fn msg(addr: &str, args: Vec<OscType>) -> OscPacket {
     OscPacket::Message(OscMessage {
@@ -23,6 +30,20 @@ pub fn message_to_osc(message: InputMessage) -> Vec<OscPacket> {
         InputMessage::Schema => {
             vec![msg("/schema", vec![])]
         }
+        InputMessage::GetAudioConfig => {
+            vec![msg("/audio-config", vec![])]
+        }
+        InputMessage::GetOutputRoutes => {
+            vec![msg("/output-routes", vec![])]
+        }
+        InputMessage::SetOutputRoute(channel, route) => {
+            let mut args = vec![OscInt(channel as i32)];
+            if let Some(route) = route {
+                args.push(OscStr(route.module.to_string()));
+                args.push(OscStr(route.port));
+            }
+            vec![msg("/output-route", args)]
+        }
         InputMessage::GetModules => {
             vec![msg("/modules", vec![])]
         }
@@ -43,6 +64,9 @@ pub fn message_to_osc(message: InputMessage) -> Vec<OscPacket> {
                 Param::Note { value } => {
                     vec![OscStr("note".to_owned()), OscInt(value as i32)]
                 }
+                Param::Text { value } => {
+                    vec![OscStr("text".to_owned()), OscStr(value)]
+                }
                 Param::Cable { module, port } => {
                     vec![
                         OscStr("cable".to_owned()),
@@ -65,6 +89,22 @@ pub fn message_to_osc(message: InputMessage) -> Vec<OscPacket> {
         InputMessage::DeleteModule(id) => {
             vec![msg("/delete-module", vec![OscStr(id.to_string())])]
         }
+        InputMessage::RandomizeModule(id, amount) => vec![msg(
+            "/randomize-module",
+            vec![OscStr(id.to_string()), OscFloat(amount)],
+        )],
+        InputMessage::Undo => {
+            vec![msg("/undo", vec![])]
+        }
+        InputMessage::Redo => {
+            vec![msg("/redo", vec![])]
+        }
+        InputMessage::GetHistoryDepth => {
+            vec![msg("/history-depth", vec![])]
+        }
+        InputMessage::GetEvents(revision) => {
+            vec![msg("/events", vec![OscLong(revision as i64)])]
+        }
         InputMessage::GetTracks => {
             todo! {}
         }
@@ -86,6 +126,116 @@ pub fn message_to_osc(message: InputMessage) -> Vec<OscPacket> {
         InputMessage::DeleteKeyframe(_, _) => {
             todo! {}
         }
+        InputMessage::ArmAutomation(id, param_name, track_id) => vec![msg(
+            "/arm-automation",
+            vec![
+                OscStr(id.to_string()),
+                OscStr(param_name),
+                OscStr(track_id.to_string()),
+            ],
+        )],
+        InputMessage::DisarmAutomation(id, param_name) => vec![msg(
+            "/disarm-automation",
+            vec![OscStr(id.to_string()), OscStr(param_name)],
+        )],
+        InputMessage::CreateScope(id, module, port, decimation) => {
+            vec![msg(
+                "/create-scope",
+                vec![
+                    OscStr(id.to_string()),
+                    OscStr(module.to_string()),
+                    OscStr(port),
+                    OscInt(decimation as i32),
+                ],
+            )]
+        }
+        InputMessage::DeleteScope(id) => {
+            vec![msg("/delete-scope", vec![OscStr(id.to_string())])]
+        }
+        InputMessage::GetSessionState => {
+            vec![msg("/session-state", vec![])]
+        }
+        InputMessage::SetSessionState(session_state) => {
+            match serde_json::to_string(&session_state) {
+                Ok(json) => vec![msg("/session-state", vec![OscStr(json)])],
+                Err(_) => vec![],
+            }
+        }
+        InputMessage::StartRecording(name) => {
+            vec![msg("/start-recording", vec![OscStr(name)])]
+        }
+        InputMessage::StopRecording => {
+            vec![msg("/stop-recording", vec![])]
+        }
+        InputMessage::ListPatches => {
+            vec![msg("/patches", vec![])]
+        }
+        InputMessage::SavePatch(name, metadata) => {
+            let mut args = vec![OscStr(name), OscStr(metadata.author.unwrap_or_default())];
+            args.extend(metadata.tags.into_iter().map(OscStr));
+            vec![msg("/save-patch", args)]
+        }
+        InputMessage::LoadPatch(name) => {
+            vec![msg("/load-patch", vec![OscStr(name)])]
+        }
+        InputMessage::SetPatchGraph(graph) => match serde_json::to_string(&graph) {
+            Ok(json) => vec![msg("/patch-graph", vec![OscStr(json)])],
+            Err(_) => vec![],
+        },
+        InputMessage::ValidatePatchGraph(graph) => match serde_json::to_string(&graph) {
+            Ok(json) => vec![msg("/validate-patch-graph", vec![OscStr(json)])],
+            Err(_) => vec![],
+        },
+        InputMessage::DeletePatch(name) => {
+            vec![msg("/delete-patch", vec![OscStr(name)])]
+        }
+        InputMessage::RenamePatch(from, to) => {
+            vec![msg("/rename-patch", vec![OscStr(from), OscStr(to)])]
+        }
+        InputMessage::CaptureSnapshot(name) => {
+            vec![msg("/capture-snapshot", vec![OscStr(name)])]
+        }
+        InputMessage::RecallSnapshot(name, duration) => {
+            vec![msg(
+                "/recall-snapshot",
+                vec![OscStr(name), OscFloat(duration.as_secs_f32())],
+            )]
+        }
+        InputMessage::DeleteSnapshot(name) => {
+            vec![msg("/delete-snapshot", vec![OscStr(name)])]
+        }
+        InputMessage::GetRecovery => {
+            vec![msg("/recovery", vec![])]
+        }
+        InputMessage::Recover => {
+            vec![msg("/recover", vec![])]
+        }
+        InputMessage::DiscardRecovery => {
+            vec![msg("/discard-recovery", vec![])]
+        }
+        InputMessage::Shutdown => {
+            vec![msg("/shutdown", vec![])]
+        }
+        InputMessage::Batch(messages) => {
+            let mut content = vec![msg("/batch", vec![])];
+            for message in messages {
+                content.extend(message_to_osc(message));
+            }
+            vec![bndl(content)]
+        }
+        InputMessage::AtRevision(revision, message) => {
+            let mut content = vec![msg("/at-revision", vec![OscLong(revision as i64)])];
+            content.extend(message_to_osc(*message));
+            vec![bndl(content)]
+        }
+        // the server knows who sent a message from where it came from
+        InputMessage::FromClient(_, message) => message_to_osc(*message),
+        InputMessage::Render(name, duration) => {
+            vec![msg(
+                "/render",
+                vec![OscStr(name), OscFloat(duration.as_secs_f32())],
+            )]
+        }
     }
 }
 
@@ -260,6 +410,9 @@ pub fn osc_to_message(packet: OscPacket, tx: &Sender<Message>) {
                                 ("note", Some(OscInt(value)), None) => Some(Param::Note {
                                     value: *value as u8,
                                 }),
+                                ("text", Some(OscStr(value)), None) => Some(Param::Text {
+                                    value: value.clone(),
+                                }),
                                 ("cable", Some(OscStr(module)), Some(OscStr(port))) => {
                                     Some(Param::Cable {
                                         module: match Uuid::parse_str(module) {
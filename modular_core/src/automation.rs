@@ -0,0 +1,83 @@
+use anyhow::{anyhow, Result};
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use uuid::Uuid;
+
+use crate::{
+    patch::Patch,
+    types::{InternalKeyframe, Param},
+};
+
+struct Armed {
+    track: Uuid,
+    // where the track's playhead was when the param was armed
+    start: Duration,
+    armed_at: Instant,
+}
+
+// Params armed for automation recording. Every change made to an armed param is written into its
+// track as a keyframe, timed from the moment it was armed.
+#[derive(Default)]
+pub struct Automation {
+    armed: HashMap<(Uuid, String), Armed>,
+}
+
+impl Automation {
+    pub fn arm(
+        &mut self,
+        patch: &Patch,
+        module: Uuid,
+        param_name: String,
+        track: Uuid,
+    ) -> Result<()> {
+        if !patch.sampleables.contains_key(&module) {
+            return Err(anyhow!("{} not found", module));
+        }
+        let start = match patch.tracks.get(&track) {
+            Some(internal_track) => internal_track.to_track().playhead,
+            None => return Err(anyhow!("track {} not found", track)),
+        };
+        self.armed.insert(
+            (module, param_name),
+            Armed {
+                track,
+                start,
+                armed_at: Instant::now(),
+            },
+        );
+        Ok(())
+    }
+
+    // returns the track the param was being recorded into
+    pub fn disarm(&mut self, module: Uuid, param_name: String) -> Option<Uuid> {
+        self.armed
+            .remove(&(module, param_name))
+            .map(|armed| armed.track)
+    }
+
+    pub fn record(&mut self, patch: &Patch, module: Uuid, param_name: &str, param: &Param) {
+        let key = (module, param_name.to_owned());
+        let (track, time) = match self.armed.get(&key) {
+            Some(armed) => (armed.track, armed.start + armed.armed_at.elapsed()),
+            None => return,
+        };
+        match param {
+            Param::Value { .. } | Param::Note { .. } => {}
+            _ => return,
+        }
+        match patch.tracks.get(&track) {
+            Some(internal_track) => internal_track.add_keyframe(InternalKeyframe::new(
+                Uuid::new_v4(),
+                track,
+                time,
+                param.to_internal_param(patch),
+            )),
+            // the track was deleted while recording
+            None => {
+                self.armed.remove(&key);
+            }
+        }
+    }
+}
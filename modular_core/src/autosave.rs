@@ -0,0 +1,87 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    patch::Patch,
+    types::{PatchGraph, SessionState},
+};
+
+pub const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+// the newest is kept even if the last write was cut short
+const AUTOSAVE_SLOTS: usize = 3;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Autosave {
+    pub saved: SystemTime,
+    pub graph: PatchGraph,
+    pub session_state: SessionState,
+}
+
+impl Autosave {
+    pub fn capture(patch: &Patch) -> Result<Self> {
+        Ok(Autosave {
+            saved: SystemTime::now(),
+            graph: patch.get_graph(),
+            session_state: patch.get_session_state()?,
+        })
+    }
+}
+
+pub struct Autosaver {
+    dir: PathBuf,
+    next_slot: usize,
+}
+
+impl Default for Autosaver {
+    fn default() -> Self {
+        Autosaver::new("autosave")
+    }
+}
+
+impl Autosaver {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Autosaver {
+            dir: dir.into(),
+            next_slot: 0,
+        }
+    }
+
+    fn path(&self, slot: usize) -> PathBuf {
+        self.dir.join(format!("autosave-{}.json", slot))
+    }
+
+    // takes an autosave already captured so the patch doesn't stay locked while the disk is written
+    pub fn save(&mut self, autosave: &Autosave) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let path = self.path(self.next_slot);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_string(autosave)?)?;
+        fs::rename(tmp, path)?;
+        self.next_slot = (self.next_slot + 1) % AUTOSAVE_SLOTS;
+        Ok(())
+    }
+
+    // the most recent autosave that can still be read
+    pub fn latest(&self) -> Option<Autosave> {
+        (0..AUTOSAVE_SLOTS)
+            .filter_map(|slot| fs::read_to_string(self.path(slot)).ok())
+            .filter_map(|json| serde_json::from_str::<Autosave>(&json).ok())
+            .max_by_key(|autosave| autosave.saved)
+    }
+
+    // called on a clean shutdown, so only a crash leaves something to recover
+    pub fn clear(&self) -> Result<()> {
+        for slot in 0..AUTOSAVE_SLOTS {
+            let path = self.path(slot);
+            if path.exists() {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -5,15 +5,25 @@ use crate::types::{ModuleSchema, SampleableConstructor};
 pub mod consts;
 pub mod core;
 pub mod oscillators;
+pub mod sequencers;
+pub mod utilities;
 pub mod utils;
 
 pub fn get_constructors() -> HashMap<String, SampleableConstructor> {
     let mut map = HashMap::new();
     core::install_constructors(&mut map);
     oscillators::install_constructors(&mut map);
+    sequencers::install_constructors(&mut map);
+    utilities::install_constructors(&mut map);
     return map;
 }
 
 pub fn schema() -> Vec<ModuleSchema> {
-    [core::schemas(), oscillators::schemas()].concat()
+    [
+        core::schemas(),
+        oscillators::schemas(),
+        sequencers::schemas(),
+        utilities::schemas(),
+    ]
+    .concat()
 }
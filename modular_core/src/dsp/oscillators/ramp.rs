@@ -3,7 +3,13 @@ use anyhow::{anyhow, Result};
 use crate::{dsp::utils::clamp, dsp::utils::wrap, types::InternalParam};
 #[derive(Default, Params)]
 struct RampOscillatorParams {
-    #[param("freq", "frequency in v/oct")]
+    #[param(
+        "freq",
+        "frequency in v/oct",
+        display = "Frequency",
+        unit = "v/oct",
+        range(0, 12)
+    )]
     freq: InternalParam,
     #[param("phase", "the phase of the oscillator, overrides freq if present")]
     phase: InternalParam,
@@ -14,6 +20,7 @@ struct RampOscillatorParams {
 pub struct RampOscillator {
     #[output("output", "signal output")]
     sample: f32,
+    #[state]
     phase: f32,
     params: RampOscillatorParams,
 }
@@ -6,13 +6,20 @@ use crate::{
         consts::{LUT_SINE, LUT_SINE_SIZE},
         utils::{clamp, interpolate},
     },
-    types::InternalParam,
+    types::{InternalParam, SmoothedParam},
 };
 
 #[derive(Default, Params)]
 struct SineOscillatorParams {
-    #[param("freq", "frequency in v/oct")]
-    freq: InternalParam,
+    #[param(
+        "freq",
+        "frequency in v/oct",
+        display = "Frequency",
+        unit = "v/oct",
+        range(0, 12),
+        smooth = "5ms"
+    )]
+    freq: SmoothedParam,
     #[param("phase", "the phase of the oscillator, overrides freq if present")]
     phase: InternalParam,
 }
@@ -22,6 +29,7 @@ struct SineOscillatorParams {
 pub struct SineOscillator {
     #[output("output", "signal output")]
     sample: f32,
+    #[state]
     phase: f32,
     params: SineOscillatorParams,
 }
@@ -31,7 +39,7 @@ impl SineOscillator {
         if self.params.phase != InternalParam::Disconnected {
             self.sample = wrap(0.0..1.0, self.params.phase.get_value())
         } else {
-            let voltage = clamp(self.params.freq.get_value_or(4.0), 12.0, 0.0);
+            let voltage = clamp(self.params.freq.next_value_or(4.0, sample_rate), 12.0, 0.0);
             let frequency = 27.5f32 * 2.0f32.powf(voltage) / sample_rate;
             // let frequency = semitones_to_ratio(voltage * 12.0) * 220.0 / SAMPLE_RATE * 100.0;
             self.phase += frequency;
@@ -0,0 +1,13 @@
+use std::collections::HashMap;
+
+use crate::types::{Module, ModuleSchema, SampleableConstructor};
+
+pub mod step_seq;
+
+pub fn install_constructors(map: &mut HashMap<String, SampleableConstructor>) {
+    step_seq::StepSeq::install_constructor(map);
+}
+
+pub fn schemas() -> Vec<ModuleSchema> {
+    vec![step_seq::StepSeq::get_schema()]
+}
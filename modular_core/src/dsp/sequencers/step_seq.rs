@@ -0,0 +1,296 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+
+use crate::{
+    dsp::utils::{clamp, parse_indexed, Rng},
+    types::{GateParam, InternalParam, Param, Params, PortSchema},
+};
+
+const STEPS: usize = 16;
+const MAX_RATCHET: usize = 4;
+
+const STEP_FIELDS: [(&str, &str); 6] = [
+    ("pitch", "pitch in v/oct"),
+    (
+        "gate",
+        "whether the step plays, off at 0 or below, on when disconnected",
+    ),
+    ("ratchet", "gates per step, 1 to 4"),
+    (
+        "gate-length",
+        "how long each gate stays high, 0 to 5 for 0% to 100%, 5 ties into the next gate",
+    ),
+    (
+        "probability",
+        "chance the step plays, 0 to 5 for 0% to 100%",
+    ),
+    ("velocity", "velocity or accent of the step, 0 to 5"),
+];
+
+#[derive(Clone, Copy, PartialEq, ParamEnum)]
+enum Direction {
+    /// first step to last
+    Forward,
+    /// last step to first
+    Backward,
+    /// forward then backward, playing the first and last steps once each time
+    PingPong,
+    /// any step, chosen at random
+    Random,
+}
+
+impl Default for Direction {
+    fn default() -> Self {
+        Direction::Forward
+    }
+}
+
+#[derive(Default, Params)]
+struct StepSeqControls {
+    #[param("clock", "advances one step on every rising edge", trigger)]
+    clock: GateParam,
+    #[param(
+        "reset",
+        "the next clock after a rising edge plays the first step",
+        trigger
+    )]
+    reset: GateParam,
+    #[param("direction", "the order steps play in")]
+    direction: Direction,
+    #[param("length", "number of steps to play, 1 to 16")]
+    length: InternalParam,
+}
+
+lazy_static! {
+    static ref SCHEMA: Vec<PortSchema> = {
+        let mut schema = StepSeqControls::get_schema().to_vec();
+        for i in 1..=STEPS {
+            let group: &'static str = Box::leak(format!("Step {}", i).into_boxed_str());
+            for (field, description) in STEP_FIELDS.iter() {
+                schema.push(PortSchema {
+                    name: Box::leak(format!("step-{}-{}", i, field).into_boxed_str()),
+                    description,
+                    group: Some(group),
+                    ..PortSchema::DEFAULT
+                });
+            }
+        }
+        schema
+    };
+}
+
+#[derive(Default)]
+struct Step {
+    pitch: InternalParam,
+    gate: InternalParam,
+    ratchet: InternalParam,
+    gate_length: InternalParam,
+    probability: InternalParam,
+    velocity: InternalParam,
+}
+
+impl Step {
+    fn field(&mut self, name: &str) -> Option<&mut InternalParam> {
+        match name {
+            "pitch" => Some(&mut self.pitch),
+            "gate" => Some(&mut self.gate),
+            "ratchet" => Some(&mut self.ratchet),
+            "gate-length" => Some(&mut self.gate_length),
+            "probability" => Some(&mut self.probability),
+            "velocity" => Some(&mut self.velocity),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct StepSeqParams {
+    controls: StepSeqControls,
+    steps: [Step; STEPS],
+}
+
+impl StepSeqParams {
+    fn param(&mut self, param_name: &str) -> Option<&mut InternalParam> {
+        let (index, field) = parse_indexed(param_name, "step")?;
+        self.steps.get_mut(index)?.field(field)
+    }
+}
+
+impl Params for StepSeqParams {
+    fn get_params_state(&self) -> HashMap<String, Param> {
+        let mut state = HashMap::new();
+        state.extend(self.controls.get_params_state());
+        for (i, step) in self.steps.iter().enumerate() {
+            let fields = [
+                &step.pitch,
+                &step.gate,
+                &step.ratchet,
+                &step.gate_length,
+                &step.probability,
+                &step.velocity,
+            ];
+            for ((field, _), param) in STEP_FIELDS.iter().zip(fields.iter()) {
+                state.insert(format!("step-{}-{}", i + 1, field), param.to_param());
+            }
+        }
+        state
+    }
+
+    fn update_param(
+        &mut self,
+        param_name: &String,
+        new_param: &InternalParam,
+        module_name: &str,
+    ) -> Result<()> {
+        if StepSeqControls::get_schema()
+            .iter()
+            .any(|port| port.name == param_name)
+        {
+            return self
+                .controls
+                .update_param(param_name, new_param, module_name);
+        }
+        match self.param(param_name) {
+            Some(param) => {
+                if *param != *new_param {
+                    *param = new_param.clone();
+                }
+                Ok(())
+            }
+            None => Err(anyhow!(
+                "{} is not a valid param name for {}",
+                param_name,
+                module_name
+            )),
+        }
+    }
+
+    fn init(&mut self) {
+        self.controls.init();
+    }
+
+    fn get_schema() -> &'static [PortSchema] {
+        &SCHEMA
+    }
+}
+
+#[derive(Default, Module)]
+#[module("stepseq", "a 16 step gate and pitch sequencer")]
+pub struct StepSeq {
+    #[output("pitch", "pitch of the current step in v/oct")]
+    pitch: f32,
+    #[output("gate", "5 while the current step is sounding, 0 otherwise")]
+    gate: f32,
+    #[output(
+        "velocity",
+        "velocity of the current step, held until the next step plays"
+    )]
+    velocity: f32,
+    // None until the first clock after creation or a reset
+    #[state]
+    position: Option<usize>,
+    #[state]
+    reversing: bool,
+    playing: bool,
+    samples_since_clock: u32,
+    clock_period: u32,
+    rng: Rng,
+    params: StepSeqParams,
+}
+
+impl StepSeq {
+    fn update(&mut self, _sample_rate: f32) -> () {
+        let controls = &mut self.params.controls;
+        let clock_high = controls.clock.is_high();
+        let clock_rising = controls.clock.rising();
+        let reset_rising = controls.reset.rising();
+
+        if reset_rising {
+            self.position = None;
+            self.reversing = false;
+        }
+
+        self.samples_since_clock = self.samples_since_clock.saturating_add(1);
+        if clock_rising {
+            if self.position.is_some() {
+                self.clock_period = self.samples_since_clock;
+            }
+            self.samples_since_clock = 0;
+            self.advance();
+        }
+
+        let position = match self.position {
+            Some(position) => position,
+            None => {
+                self.gate = 0.0;
+                return;
+            }
+        };
+        let step = &self.params.steps[position];
+        self.pitch = step.pitch.get_value();
+
+        let ratchet = clamp(
+            1,
+            MAX_RATCHET as u32,
+            step.ratchet.get_value_or(1.0).round() as u32,
+        );
+        let gate_length = step.gate_length.get_value_or(2.5) / 5.0;
+        let sounding = if self.clock_period == 0 {
+            // tempo not known yet, follow the clock
+            self.playing && clock_high
+        } else if gate_length >= 1.0 {
+            self.playing
+        } else {
+            let division = (self.clock_period / ratchet).max(1);
+            let elapsed = (self.samples_since_clock % division) as f32;
+            self.playing && elapsed < division as f32 * gate_length
+        };
+        self.gate = if sounding { 5.0 } else { 0.0 };
+    }
+
+    fn advance(&mut self) {
+        let length = clamp(
+            1,
+            STEPS as u32,
+            self.params
+                .controls
+                .length
+                .get_value_or(STEPS as f32)
+                .round() as u32,
+        ) as usize;
+        let position = match (self.position, self.params.controls.direction) {
+            (None, Direction::Backward) => length - 1,
+            (None, _) => 0,
+            (Some(position), Direction::Backward) => (position + length - 1) % length,
+            (Some(position), Direction::PingPong) => {
+                if length == 1 {
+                    0
+                } else {
+                    // the length may have been lowered since the last step, which then turns back
+                    let position = position.min(length - 1);
+                    if position == 0 {
+                        self.reversing = false;
+                    } else if position >= length - 1 {
+                        self.reversing = true;
+                    }
+                    if self.reversing {
+                        position - 1
+                    } else {
+                        position + 1
+                    }
+                }
+            }
+            (Some(_), Direction::Random) => self.rng.next() as usize % length,
+            (Some(position), Direction::Forward) => (position + 1) % length,
+        };
+        self.position = Some(position);
+
+        let step = &self.params.steps[position];
+        let gate_on = step.gate == InternalParam::Disconnected || step.gate.get_value() > 0.0;
+        let probability = step.probability.get_value_or(5.0) / 5.0;
+        self.playing = gate_on && self.rng.next_f32() < probability;
+        if self.playing {
+            self.velocity = step.velocity.get_value_or(5.0);
+        }
+    }
+}
@@ -0,0 +1,18 @@
+use std::collections::HashMap;
+
+use crate::types::{Module, ModuleSchema, SampleableConstructor};
+
+pub mod mod_matrix;
+pub mod script;
+
+pub fn install_constructors(map: &mut HashMap<String, SampleableConstructor>) {
+    script::Script::install_constructor(map);
+    mod_matrix::ModMatrix::install_constructor(map);
+}
+
+pub fn schemas() -> Vec<ModuleSchema> {
+    vec![
+        script::Script::get_schema(),
+        mod_matrix::ModMatrix::get_schema(),
+    ]
+}
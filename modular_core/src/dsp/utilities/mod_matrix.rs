@@ -0,0 +1,185 @@
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+
+use crate::{
+    dsp::utils::parse_indexed,
+    types::{InternalParam, Param, Params, PortSchema},
+};
+
+const SOURCES: usize = 4;
+const SLOTS: usize = 8;
+
+const SLOT_FIELDS: [(&str, &str); 4] = [
+    ("source", "which source this slot reads, 1 to 4"),
+    ("destination", "which output this slot adds into, 1 to 4"),
+    ("depth", "amount, -5 to 5 for -100% to 100%"),
+    (
+        "curve",
+        "response curve, 0 is linear, positive is exponential, negative is logarithmic",
+    ),
+];
+
+lazy_static! {
+    static ref SCHEMA: Vec<PortSchema> = {
+        let mut schema = Vec::new();
+        for i in 1..=SOURCES {
+            schema.push(PortSchema {
+                name: Box::leak(format!("source-{}", i).into_boxed_str()),
+                description: "a modulation source",
+                group: Some("Sources"),
+                ..PortSchema::DEFAULT
+            });
+        }
+        for i in 1..=SLOTS {
+            let group: &'static str = Box::leak(format!("Slot {}", i).into_boxed_str());
+            for (field, description) in SLOT_FIELDS.iter() {
+                schema.push(PortSchema {
+                    name: Box::leak(format!("slot-{}-{}", i, field).into_boxed_str()),
+                    description,
+                    group: Some(group),
+                    ..PortSchema::DEFAULT
+                });
+            }
+        }
+        schema
+    };
+}
+
+#[derive(Default)]
+struct Slot {
+    source: InternalParam,
+    destination: InternalParam,
+    depth: InternalParam,
+    curve: InternalParam,
+}
+
+impl Slot {
+    fn field(&mut self, name: &str) -> Option<&mut InternalParam> {
+        match name {
+            "source" => Some(&mut self.source),
+            "destination" => Some(&mut self.destination),
+            "depth" => Some(&mut self.depth),
+            "curve" => Some(&mut self.curve),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct ModMatrixParams {
+    sources: [InternalParam; SOURCES],
+    slots: [Slot; SLOTS],
+}
+
+impl ModMatrixParams {
+    fn param(&mut self, param_name: &str) -> Option<&mut InternalParam> {
+        if let Some((index, "")) = parse_indexed(param_name, "source") {
+            return self.sources.get_mut(index);
+        }
+        let (index, field) = parse_indexed(param_name, "slot")?;
+        self.slots.get_mut(index)?.field(field)
+    }
+}
+
+impl Params for ModMatrixParams {
+    fn get_params_state(&self) -> HashMap<String, Param> {
+        let mut state = HashMap::new();
+        for (i, source) in self.sources.iter().enumerate() {
+            state.insert(format!("source-{}", i + 1), source.to_param());
+        }
+        for (i, slot) in self.slots.iter().enumerate() {
+            let fields = [&slot.source, &slot.destination, &slot.depth, &slot.curve];
+            for ((field, _), param) in SLOT_FIELDS.iter().zip(fields.iter()) {
+                state.insert(format!("slot-{}-{}", i + 1, field), param.to_param());
+            }
+        }
+        state
+    }
+
+    fn update_param(
+        &mut self,
+        param_name: &String,
+        new_param: &InternalParam,
+        module_name: &str,
+    ) -> Result<()> {
+        match self.param(param_name) {
+            Some(param) => {
+                if *param != *new_param {
+                    *param = new_param.clone();
+                }
+                Ok(())
+            }
+            None => Err(anyhow!(
+                "{} is not a valid param name for {}",
+                param_name,
+                module_name
+            )),
+        }
+    }
+
+    fn get_schema() -> &'static [PortSchema] {
+        &SCHEMA
+    }
+}
+
+// maps a 1-based index param onto an array index
+fn index(param: &InternalParam, len: usize) -> Option<usize> {
+    if *param == InternalParam::Disconnected {
+        return None;
+    }
+    let index = param.get_value().round() as i32 - 1;
+    if index >= 0 && (index as usize) < len {
+        Some(index as usize)
+    } else {
+        None
+    }
+}
+
+fn apply_curve(value: f32, curve: f32) -> f32 {
+    if curve == 0.0 {
+        return value;
+    }
+    let normalized = (value / 5.0).max(-1.0).min(1.0);
+    let exponent = 2.0f32.powf(curve / 2.5);
+    5.0 * normalized.signum() * normalized.abs().powf(exponent)
+}
+
+#[derive(Default, Module)]
+#[module("modmatrix", "routes 4 sources to 4 outputs through 8 slots")]
+pub struct ModMatrix {
+    #[output("output-1", "sum of the slots with destination 1")]
+    output1: f32,
+    #[output("output-2", "sum of the slots with destination 2")]
+    output2: f32,
+    #[output("output-3", "sum of the slots with destination 3")]
+    output3: f32,
+    #[output("output-4", "sum of the slots with destination 4")]
+    output4: f32,
+    params: ModMatrixParams,
+}
+
+impl ModMatrix {
+    fn update(&mut self, _sample_rate: f32) -> () {
+        let mut sources = [0.0; SOURCES];
+        for (value, source) in sources.iter_mut().zip(self.params.sources.iter()) {
+            *value = source.get_value();
+        }
+
+        let mut outputs = [0.0; 4];
+        for slot in self.params.slots.iter() {
+            if let (Some(source), Some(destination)) = (
+                index(&slot.source, SOURCES),
+                index(&slot.destination, outputs.len()),
+            ) {
+                let depth = slot.depth.get_value() / 5.0;
+                outputs[destination] +=
+                    apply_curve(sources[source], slot.curve.get_value()) * depth;
+            }
+        }
+
+        self.output1 = outputs[0];
+        self.output2 = outputs[1];
+        self.output3 = outputs[2];
+        self.output4 = outputs[3];
+    }
+}
@@ -0,0 +1,279 @@
+use anyhow::{anyhow, Result};
+use rhai::{Array, Dynamic, Engine, ImmutableString, Map, Scope, AST, FLOAT, INT};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::{collections::HashMap, sync::Arc};
+
+use crate::{
+    history::PatchOp,
+    types::{CompiledScript, InternalParam, Param, Params, PortSchema},
+};
+
+// Upper bound on the work a script can do per sample, small enough that a few scripts fit in a
+// sample's time. A script that goes over is stopped and outputs silence for that sample, so a
+// runaway loop can't hang the audio thread.
+const MAX_OPERATIONS: u64 = 250;
+
+const INPUT_NAMES: [&str; 4] = ["in1", "in2", "in3", "in4"];
+const OUTPUT_NAMES: [&str; 4] = ["out1", "out2", "out3", "out4"];
+
+// Compiles the code of every script among `ops`, so applying them once the patch is locked only
+// has to swap in the result. Fails on the first script that doesn't compile.
+pub fn compile_scripts(ops: &mut [PatchOp]) -> Result<()> {
+    for op in ops.iter_mut() {
+        if let PatchOp::UpdateParam(_, param_name, Param::Text { value }, compiled) = op {
+            if param_name == "code" && compiled.is_none() {
+                *compiled = Some(compile(value)?);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn compile(code: &str) -> Result<CompiledScript> {
+    Ok(CompiledScript(Arc::new(
+        ScriptEngine::default().0.compile(code)?,
+    )))
+}
+
+// The script's variables. Only the state map lasts from one sample to the next, so that's all a
+// session captures.
+#[derive(Default)]
+struct ScriptScope(Scope<'static>);
+
+impl Serialize for ScriptScope {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        match self.0.get_value::<Dynamic>("state") {
+            Some(state) => to_json(&state).serialize(serializer),
+            None => Value::Object(serde_json::Map::new()).serialize(serializer),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ScriptScope {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Self, D::Error> {
+        let state = from_json(&Value::deserialize(deserializer)?);
+        let mut scope = Scope::new();
+        if state.is::<Map>() {
+            scope.push("state", state);
+        } else {
+            scope.push("state", Map::new());
+        }
+        Ok(ScriptScope(scope))
+    }
+}
+
+// values rhai has that json doesn't, such as functions, become null
+fn to_json(value: &Dynamic) -> Value {
+    if let Ok(value) = value.as_float() {
+        serde_json::Number::from_f64(value).map_or(Value::Null, Value::Number)
+    } else if let Ok(value) = value.as_int() {
+        Value::from(value)
+    } else if let Ok(value) = value.as_bool() {
+        Value::Bool(value)
+    } else if let Some(value) = value.clone().try_cast::<ImmutableString>() {
+        Value::String(value.to_string())
+    } else if let Some(array) = value.clone().try_cast::<Array>() {
+        Value::Array(array.iter().map(to_json).collect())
+    } else if let Some(map) = value.clone().try_cast::<Map>() {
+        Value::Object(
+            map.iter()
+                .map(|(key, value)| (key.to_string(), to_json(value)))
+                .collect(),
+        )
+    } else {
+        Value::Null
+    }
+}
+
+fn from_json(value: &Value) -> Dynamic {
+    match value {
+        Value::Null => Dynamic::UNIT,
+        Value::Bool(value) => Dynamic::from(*value),
+        Value::Number(number) => match number.as_i64() {
+            Some(value) => Dynamic::from(value as INT),
+            None => Dynamic::from(number.as_f64().unwrap_or(0.0) as FLOAT),
+        },
+        Value::String(value) => Dynamic::from(value.clone()),
+        Value::Array(values) => Dynamic::from(values.iter().map(from_json).collect::<Array>()),
+        Value::Object(values) => Dynamic::from(
+            values
+                .iter()
+                .map(|(key, value)| (key.as_str().into(), from_json(value)))
+                .collect::<Map>(),
+        ),
+    }
+}
+
+struct ScriptEngine(Engine);
+
+impl Default for ScriptEngine {
+    fn default() -> Self {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        ScriptEngine(engine)
+    }
+}
+
+#[derive(Default)]
+struct ScriptParams {
+    input1: InternalParam,
+    input2: InternalParam,
+    input3: InternalParam,
+    input4: InternalParam,
+    code: InternalParam,
+    ast: Option<Arc<AST>>,
+}
+
+impl Params for ScriptParams {
+    fn get_params_state(&self) -> HashMap<String, Param> {
+        let mut state = HashMap::new();
+        state.insert("input-1".to_owned(), self.input1.to_param());
+        state.insert("input-2".to_owned(), self.input2.to_param());
+        state.insert("input-3".to_owned(), self.input3.to_param());
+        state.insert("input-4".to_owned(), self.input4.to_param());
+        state.insert("code".to_owned(), self.code.to_param());
+        state
+    }
+
+    fn update_param(
+        &mut self,
+        param_name: &String,
+        new_param: &InternalParam,
+        module_name: &str,
+    ) -> Result<()> {
+        match param_name.as_str() {
+            "input-1" => self.input1 = new_param.clone(),
+            "input-2" => self.input2 = new_param.clone(),
+            "input-3" => self.input3 = new_param.clone(),
+            "input-4" => self.input4 = new_param.clone(),
+            "code" => {
+                if self.code != *new_param {
+                    self.ast = match new_param {
+                        InternalParam::Text {
+                            compiled: Some(compiled),
+                            ..
+                        } => Some(compiled.0.clone()),
+                        // only patches that aren't running, e.g. ones being validated or
+                        // rendered, are set without compiling first
+                        InternalParam::Text { value, .. } => Some(compile(value)?.0),
+                        _ => None,
+                    };
+                    self.code = new_param.clone();
+                }
+            }
+            _ => {
+                return Err(anyhow!(
+                    "{} is not a valid param name for {}",
+                    param_name,
+                    module_name
+                ))
+            }
+        };
+        Ok(())
+    }
+
+    fn get_schema() -> &'static [PortSchema] {
+        &[
+            PortSchema {
+                name: "input-1",
+                description: "a signal input, available to the script as in1",
+                ..PortSchema::DEFAULT
+            },
+            PortSchema {
+                name: "input-2",
+                description: "a signal input, available to the script as in2",
+                ..PortSchema::DEFAULT
+            },
+            PortSchema {
+                name: "input-3",
+                description: "a signal input, available to the script as in3",
+                ..PortSchema::DEFAULT
+            },
+            PortSchema {
+                name: "input-4",
+                description: "a signal input, available to the script as in4",
+                ..PortSchema::DEFAULT
+            },
+            PortSchema {
+                name: "code",
+                description: "rhai run every sample, sets out1 to out4, the state map persists",
+                ..PortSchema::DEFAULT
+            },
+        ]
+    }
+}
+
+#[derive(Default, Module)]
+#[module("script", "a per-sample DSP function written in rhai")]
+pub struct Script {
+    #[output("output-1", "the value of out1")]
+    sample1: f32,
+    #[output("output-2", "the value of out2")]
+    sample2: f32,
+    #[output("output-3", "the value of out3")]
+    sample3: f32,
+    #[output("output-4", "the value of out4")]
+    sample4: f32,
+    engine: ScriptEngine,
+    #[state]
+    scope: ScriptScope,
+    params: ScriptParams,
+}
+
+impl Script {
+    fn update(&mut self, sample_rate: f32) -> () {
+        let ast = match self.params.ast {
+            Some(ref ast) => ast,
+            None => {
+                self.set_outputs([0.0; 4]);
+                return;
+            }
+        };
+
+        let scope = &mut self.scope.0;
+        if scope.is_empty() {
+            scope.push("state", Map::new());
+        }
+        let base = scope.len();
+
+        let inputs = [
+            &self.params.input1,
+            &self.params.input2,
+            &self.params.input3,
+            &self.params.input4,
+        ];
+        scope.push_constant("sample_rate", sample_rate as FLOAT);
+        for (name, input) in INPUT_NAMES.iter().zip(inputs.iter()) {
+            scope.push(*name, input.get_value() as FLOAT);
+        }
+        for name in OUTPUT_NAMES.iter() {
+            scope.push(*name, 0.0 as FLOAT);
+        }
+
+        let mut outputs = [0.0; 4];
+        if self.engine.0.run_ast_with_scope(scope, ast).is_ok() {
+            for (output, name) in outputs.iter_mut().zip(OUTPUT_NAMES.iter()) {
+                *output = scope
+                    .get_value::<Dynamic>(name)
+                    .and_then(|value| {
+                        value
+                            .as_float()
+                            .ok()
+                            .or_else(|| value.as_int().ok().map(|value| value as FLOAT))
+                    })
+                    .unwrap_or(0.0) as f32;
+            }
+        }
+        // drop everything the script declared at the top level so the scope doesn't grow every sample
+        scope.rewind(base);
+        self.set_outputs(outputs);
+    }
+
+    fn set_outputs(&mut self, outputs: [f32; 4]) {
+        self.sample1 = outputs[0];
+        self.sample2 = outputs[1];
+        self.sample3 = outputs[2];
+        self.sample4 = outputs[3];
+    }
+}
@@ -1,5 +1,16 @@
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
 use crate::dsp::consts::{LUT_PITCH_RATIO_HIGH, LUT_PITCH_RATIO_LOW};
 
+// a fixed nonzero seed for when one is needed
+const DEFAULT_SEED: u32 = 0x9E37_79B9;
+
+// counts the generators seeded so far, so ones created in the same instant still differ
+static SEEDED: AtomicU32 = AtomicU32::new(0);
+
 fn make_integral_fractional(x: f32) -> (i32, f32) {
     let integral: i32 = x as i32;
     let fractional: f32 = x - (integral as f32);
@@ -49,3 +60,48 @@ where
     }
     val
 }
+
+// Splits names like "step-3-gate" into a zero based index and the remainder, for modules that
+// expose numbered groups of params.
+pub fn parse_indexed<'a>(name: &'a str, prefix: &str) -> Option<(usize, &'a str)> {
+    let rest = name.strip_prefix(prefix)?.strip_prefix('-')?;
+    let mut parts = rest.splitn(2, '-');
+    let index = parts.next()?.parse::<usize>().ok()?.checked_sub(1)?;
+    Some((index, parts.next().unwrap_or("")))
+}
+
+// xorshift32, cheap enough to call per sample from the audio thread
+pub struct Rng(u32);
+
+impl Rng {
+    pub fn new(seed: u32) -> Self {
+        // xorshift never leaves 0
+        Rng(if seed == 0 { DEFAULT_SEED } else { seed })
+    }
+
+    pub fn next(&mut self) -> u32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        x
+    }
+
+    // uniform in [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next() >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+// Seeded differently every time, so e.g. two sequencers in random mode don't play the same steps.
+impl Default for Rng {
+    fn default() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .subsec_nanos();
+        let count = SEEDED.fetch_add(1, Ordering::Relaxed);
+        Rng::new(nanos ^ count.wrapping_mul(DEFAULT_SEED))
+    }
+}
@@ -0,0 +1,111 @@
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    thread,
+    time::SystemTime,
+};
+
+use crate::history::PatchOp;
+
+// older events are only in the file, if there is one
+const MAX_EVENTS: usize = 10_000;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PatchEvent {
+    pub revision: u64,
+    pub time: SystemTime,
+    // the address of the client that made the change, None for the patch file or a recovery
+    #[serde(default)]
+    pub client: Option<String>,
+    pub ops: Vec<PatchOp>,
+}
+
+// The most recent changes applied to the patch, in order. When given a path every change is also
+// appended to it as json lines, so a session can be replayed after the server has gone away. The
+// file is written on its own thread since changes are recorded while the patch is locked.
+pub struct EventLog {
+    events: VecDeque<PatchEvent>,
+    writer: Option<Sender<PatchEvent>>,
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        EventLog::new(None)
+    }
+}
+
+impl EventLog {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        let writer = path.map(|path| {
+            let (sender, receiver) = unbounded();
+            thread::spawn(move || write(path, receiver));
+            sender
+        });
+        EventLog {
+            events: VecDeque::new(),
+            writer,
+        }
+    }
+
+    pub fn record(&mut self, revision: u64, client: Option<String>, ops: &[PatchOp]) {
+        let event = PatchEvent {
+            revision,
+            time: SystemTime::now(),
+            client,
+            ops: ops.to_vec(),
+        };
+        if let Some(ref writer) = self.writer {
+            let _ = writer.send(event.clone());
+        }
+        if self.events.len() == MAX_EVENTS {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    // events after `revision`, oldest first
+    pub fn since(&self, revision: u64) -> Vec<PatchEvent> {
+        self.events
+            .iter()
+            .filter(|event| event.revision > revision)
+            .cloned()
+            .collect()
+    }
+}
+
+// Runs until the log is dropped. The file is only created once there's something to write.
+fn write(path: PathBuf, receiver: Receiver<PatchEvent>) {
+    let mut file: Option<BufWriter<File>> = None;
+    for event in receiver.iter() {
+        let written = open(&path, &mut file).and_then(|file| {
+            writeln!(file, "{}", serde_json::to_string(&event)?)?;
+            // flushed whenever it catches up, so the file is never far behind
+            if receiver.is_empty() {
+                file.flush()?;
+            }
+            Ok(())
+        });
+        if let Err(err) = written {
+            eprintln!("error: writing {}: {}", path.display(), err);
+        }
+    }
+}
+
+fn open<'a>(
+    path: &Path,
+    file: &'a mut Option<BufWriter<File>>,
+) -> anyhow::Result<&'a mut BufWriter<File>> {
+    if file.is_none() {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        *file = Some(BufWriter::new(
+            OpenOptions::new().create(true).append(true).open(path)?,
+        ));
+    }
+    Ok(file.as_mut().unwrap())
+}
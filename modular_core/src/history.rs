@@ -0,0 +1,345 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use uuid::Uuid;
+
+use crate::{
+    dsp::get_constructors,
+    patch::Patch,
+    types::{CompiledScript, InternalParam, Param, PatchGraph, ROOT_ID},
+};
+
+const HISTORY_SIZE: usize = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum PatchOp {
+    CreateModule(String, Uuid),
+    DeleteModule(Uuid),
+    // a script's code can be compiled ahead of applying the op, see compile_scripts
+    UpdateParam(Uuid, String, Param, #[serde(skip)] Option<CompiledScript>),
+}
+
+pub fn apply(patch: &mut Patch, op: &PatchOp, sample_rate: f32) -> Result<()> {
+    match op {
+        PatchOp::CreateModule(module_type, id) => match get_constructors().get(module_type) {
+            Some(constructor) => {
+                let module = constructor(id, sample_rate)?;
+                patch.sampleables.insert(*id, module);
+                Ok(())
+            }
+            None => Err(anyhow!("{} is not a valid module type", module_type)),
+        },
+        PatchOp::DeleteModule(id) => {
+            patch.sampleables.remove(id);
+            Ok(())
+        }
+        PatchOp::UpdateParam(id, param_name, param, compiled) => match patch.sampleables.get(id) {
+            Some(module) => {
+                let mut internal_param = param.to_internal_param(patch);
+                if let InternalParam::Text {
+                    compiled: ref mut internal_compiled,
+                    ..
+                } = internal_param
+                {
+                    *internal_compiled = compiled.clone();
+                }
+                module.update_param(param_name, &internal_param)
+            }
+            None => Err(anyhow!("{} not found", id)),
+        },
+    }
+}
+
+// Modules are all created before any params are set so cables can point at modules later in the
+// graph.
+pub fn graph_ops(graph: &PatchGraph) -> Vec<PatchOp> {
+    let mut ops: Vec<PatchOp> = graph
+        .iter()
+        .map(|(id, config)| PatchOp::CreateModule(config.module_type.clone(), *id))
+        .collect();
+    for (id, config) in graph.iter() {
+        for (param_name, param) in config.params.iter() {
+            ops.push(PatchOp::UpdateParam(
+                *id,
+                param_name.clone(),
+                param.clone(),
+                None,
+            ));
+        }
+    }
+    ops
+}
+
+// the modules `ops` change, in the order they're first changed
+pub fn touched(ops: &[PatchOp]) -> Vec<Uuid> {
+    let mut ids: Vec<Uuid> = vec![];
+    for op in ops.iter() {
+        let id = match op {
+            PatchOp::CreateModule(_, id) | PatchOp::DeleteModule(id) => id,
+            PatchOp::UpdateParam(id, _, _, _) => id,
+        };
+        if !ids.contains(id) {
+            ids.push(*id);
+        }
+    }
+    ids
+}
+
+// Ops that replace everything in `patch` with `graph`, adding a root if `graph` has none.
+pub fn load_ops(patch: &Patch, graph: &PatchGraph) -> Vec<PatchOp> {
+    let mut ops: Vec<PatchOp> = patch
+        .sampleables
+        .keys()
+        .map(|id| PatchOp::DeleteModule(*id))
+        .collect();
+    if !graph.contains_key(&ROOT_ID) {
+        ops.push(PatchOp::CreateModule("signal".to_owned(), *ROOT_ID));
+    }
+    ops.extend(graph_ops(graph));
+    ops
+}
+
+// Ops that recreate a module exactly as it is now, including cables other modules have into it,
+// since those only hold weak references and won't follow a newly constructed module.
+fn restore_ops(patch: &Patch, id: &Uuid) -> Vec<PatchOp> {
+    let module = match patch.sampleables.get(id) {
+        Some(module) => module,
+        None => return vec![PatchOp::DeleteModule(*id)],
+    };
+    let state = module.get_state();
+    let mut ops = vec![PatchOp::CreateModule(state.module_type, *id)];
+    for (param_name, param) in state.params {
+        if param != Param::Disconnected {
+            ops.push(PatchOp::UpdateParam(*id, param_name, param, None));
+        }
+    }
+    for (other_id, other) in patch.sampleables.iter() {
+        if other_id == id {
+            continue;
+        }
+        for (param_name, param) in other.get_state().params {
+            if let Param::Cable { ref module, .. } = param {
+                if module == id {
+                    ops.push(PatchOp::UpdateParam(*other_id, param_name, param, None));
+                }
+            }
+        }
+    }
+    ops
+}
+
+fn inverse(patch: &Patch, op: &PatchOp) -> Result<Vec<PatchOp>> {
+    match op {
+        PatchOp::CreateModule(_, id) | PatchOp::DeleteModule(id) => Ok(restore_ops(patch, id)),
+        PatchOp::UpdateParam(id, param_name, _, _) => match patch.sampleables.get(id) {
+            Some(module) => Ok(vec![PatchOp::UpdateParam(
+                *id,
+                param_name.clone(),
+                module
+                    .get_state()
+                    .params
+                    .remove(param_name)
+                    .unwrap_or(Param::Disconnected),
+                None,
+            )]),
+            None => Err(anyhow!("{} not found", id)),
+        },
+    }
+}
+
+// Applies all of `ops` or, if any of them fails, rolls back the ones already applied. Returns the
+// ops that undo them. A rollback op that fails is skipped so the rest still get applied, and its
+// error is returned along with the original one.
+fn apply_all(patch: &mut Patch, ops: &[PatchOp], sample_rate: f32) -> Result<Vec<PatchOp>> {
+    let mut inverse_ops = Vec::new();
+    for op in ops.iter() {
+        let result = inverse(patch, op).and_then(|inverse_op| {
+            apply(patch, op, sample_rate)?;
+            Ok(inverse_op)
+        });
+        match result {
+            Ok(inverse_op) => inverse_ops.push(inverse_op),
+            Err(err) => {
+                let rollback_err = inverse_ops
+                    .iter()
+                    .rev()
+                    .flatten()
+                    .filter_map(|inverse_op| apply(patch, inverse_op, sample_rate).err())
+                    .last();
+                return match rollback_err {
+                    Some(rollback_err) => Err(anyhow!(
+                        "{}, and rolling back failed: {}",
+                        err,
+                        rollback_err
+                    )),
+                    None => Err(err),
+                };
+            }
+        }
+    }
+    Ok(inverse_ops.into_iter().rev().flatten().collect())
+}
+
+struct Transaction {
+    ops: Vec<PatchOp>,
+    inverse: Vec<PatchOp>,
+}
+
+#[derive(Default)]
+pub struct History {
+    undo: VecDeque<Transaction>,
+    redo: Vec<Transaction>,
+}
+
+impl History {
+    // Applies all of `ops` or, if any of them fails, none of them.
+    pub fn apply(&mut self, patch: &mut Patch, ops: Vec<PatchOp>, sample_rate: f32) -> Result<()> {
+        let inverse = apply_all(patch, &ops, sample_rate)?;
+        self.redo.clear();
+        self.push_undo(Transaction { ops, inverse });
+        Ok(())
+    }
+
+    // Undo and redo return the ops they applied. If one of them fails the patch is put back as it
+    // was and the step stays where it was, so it can be tried again.
+    pub fn undo(&mut self, patch: &mut Patch, sample_rate: f32) -> Result<Vec<PatchOp>> {
+        let transaction = self.undo.pop_back().ok_or(anyhow!("nothing to undo"))?;
+        if let Err(err) = apply_all(patch, &transaction.inverse, sample_rate) {
+            self.undo.push_back(transaction);
+            return Err(err);
+        }
+        let ops = transaction.inverse.clone();
+        self.redo.push(transaction);
+        Ok(ops)
+    }
+
+    pub fn redo(&mut self, patch: &mut Patch, sample_rate: f32) -> Result<Vec<PatchOp>> {
+        let transaction = self.redo.pop().ok_or(anyhow!("nothing to redo"))?;
+        if let Err(err) = apply_all(patch, &transaction.ops, sample_rate) {
+            self.redo.push(transaction);
+            return Err(err);
+        }
+        let ops = transaction.ops.clone();
+        self.push_undo(transaction);
+        Ok(ops)
+    }
+
+    // the ops undo and redo would apply next, empty if there's nothing to undo or redo
+    pub fn next_undo(&mut self) -> &mut [PatchOp] {
+        self.undo
+            .back_mut()
+            .map_or(&mut [], |transaction| &mut transaction.inverse)
+    }
+
+    pub fn next_redo(&mut self) -> &mut [PatchOp] {
+        self.redo
+            .last_mut()
+            .map_or(&mut [], |transaction| &mut transaction.ops)
+    }
+
+    // how many steps can be undone and redone
+    pub fn depth(&self) -> (usize, usize) {
+        (self.undo.len(), self.redo.len())
+    }
+
+    fn push_undo(&mut self, transaction: Transaction) {
+        if self.undo.len() == HISTORY_SIZE {
+            self.undo.pop_front();
+        }
+        self.undo.push_back(transaction);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    const SAMPLE_RATE: f32 = 48000.0;
+
+    fn params(patch: &Patch, id: &Uuid) -> HashMap<String, Param> {
+        patch.sampleables[id].get_state().params
+    }
+
+    #[test]
+    fn undo_and_redo_delete_with_incoming_cables() {
+        let mut patch = Patch::new(HashMap::new(), HashMap::new());
+        let mut history = History::default();
+        let source = Uuid::new_v4();
+        let sink = Uuid::new_v4();
+        let cable = Param::Cable {
+            module: source,
+            port: "output".to_owned(),
+        };
+        history
+            .apply(
+                &mut patch,
+                vec![
+                    PatchOp::CreateModule("sine-oscillator".to_owned(), source),
+                    PatchOp::CreateModule("sine-oscillator".to_owned(), sink),
+                    PatchOp::UpdateParam(sink, "phase".to_owned(), cable.clone(), None),
+                ],
+                SAMPLE_RATE,
+            )
+            .unwrap();
+
+        history
+            .apply(&mut patch, vec![PatchOp::DeleteModule(source)], SAMPLE_RATE)
+            .unwrap();
+        assert!(!patch.sampleables.contains_key(&source));
+        assert_eq!(params(&patch, &sink)["phase"], Param::Disconnected);
+
+        history.undo(&mut patch, SAMPLE_RATE).unwrap();
+        assert!(patch.sampleables.contains_key(&source));
+        assert_eq!(params(&patch, &sink)["phase"], cable);
+
+        history.redo(&mut patch, SAMPLE_RATE).unwrap();
+        assert!(!patch.sampleables.contains_key(&source));
+        assert_eq!(params(&patch, &sink)["phase"], Param::Disconnected);
+        assert_eq!(history.depth(), (2, 0));
+    }
+
+    #[test]
+    fn failing_op_rolls_back_batch() {
+        let mut patch = Patch::new(HashMap::new(), HashMap::new());
+        let mut history = History::default();
+        let existing = Uuid::new_v4();
+        history
+            .apply(
+                &mut patch,
+                vec![PatchOp::CreateModule(
+                    "sine-oscillator".to_owned(),
+                    existing,
+                )],
+                SAMPLE_RATE,
+            )
+            .unwrap();
+        let before = params(&patch, &existing);
+
+        let created = Uuid::new_v4();
+        let result = history.apply(
+            &mut patch,
+            vec![
+                PatchOp::CreateModule("sine-oscillator".to_owned(), created),
+                PatchOp::UpdateParam(
+                    existing,
+                    "freq".to_owned(),
+                    Param::Value { value: 4.0 },
+                    None,
+                ),
+                PatchOp::UpdateParam(
+                    Uuid::new_v4(),
+                    "freq".to_owned(),
+                    Param::Value { value: 4.0 },
+                    None,
+                ),
+                PatchOp::DeleteModule(existing),
+            ],
+            SAMPLE_RATE,
+        );
+        assert!(result.is_err());
+        assert!(!patch.sampleables.contains_key(&created));
+        assert_eq!(params(&patch, &existing), before);
+        assert_eq!(history.depth(), (1, 0));
+    }
+}
@@ -11,20 +11,37 @@ extern crate parking_lot;
 extern crate serde;
 extern crate serde_json;
 
+pub mod automation;
+pub mod autosave;
 pub mod dsp;
+pub mod event_log;
+pub mod history;
+pub mod library;
 pub mod message;
+pub mod metrics;
 pub mod patch;
+pub mod randomize;
+pub mod recording;
+pub mod render;
+pub mod scope;
 mod sequence;
+pub mod snapshot;
 pub mod types;
+pub mod validation;
 
-use std::thread;
+use std::{path::PathBuf, thread};
 
-use cpal::traits::{DeviceTrait, HostTrait};
+use anyhow::{anyhow, Result};
+use cpal::{
+    traits::{DeviceTrait, HostTrait},
+    BufferSize, SampleRate, SupportedBufferSize, SupportedStreamConfig,
+};
 pub use crossbeam_channel;
 use crossbeam_channel::{Receiver, Sender};
 use message::{InputMessage, OutputMessage};
 use patch::Patch;
 use thread::JoinHandle;
+use types::AudioConfig;
 pub use uuid;
 
 pub struct Modular;
@@ -33,22 +50,129 @@ impl Modular {
     pub fn spawn(
         incoming_rx: Receiver<InputMessage>,
         outgoing_tx: Sender<OutputMessage>,
+        host: Option<String>,
+        audio_config: AudioConfig,
+        event_log: Option<PathBuf>,
+        recordings: PathBuf,
     ) -> JoinHandle<anyhow::Result<()>> {
-        // let host = cpal::host_from_id(cpal::HostId::Asio).expect("failed to initialize ASIO host");
-        let host = cpal::default_host();
+        // failing to open the output ends the thread with the error rather than panicking
+        let output = get_host(host.as_deref()).and_then(|host| {
+            let device = host.default_output_device().ok_or_else(|| {
+                anyhow!(
+                    "audio host {} has no output device, try one of {:?}",
+                    host.id().name(),
+                    cpal::available_hosts()
+                        .iter()
+                        .map(|id| id.name())
+                        .collect::<Vec<_>>()
+                )
+            })?;
+            let (config, buffer_size) = stream_config(&device, &audio_config)?;
+            Ok((device, config, buffer_size))
+        });
 
-        let device = host.default_output_device().unwrap();
-
-        let config = device.default_output_config().unwrap();
-
-        thread::spawn(move || match config.sample_format() {
-            cpal::SampleFormat::I16 => Patch::run::<i16>(&device, config, incoming_rx, outgoing_tx),
-            cpal::SampleFormat::U16 => Patch::run::<u16>(&device, config, incoming_rx, outgoing_tx),
-            cpal::SampleFormat::F32 => Patch::run::<f32>(&device, config, incoming_rx, outgoing_tx),
+        thread::spawn(move || {
+            let (device, config, buffer_size) = output?;
+            match config.sample_format() {
+                cpal::SampleFormat::I16 => Patch::run::<i16>(
+                    &device,
+                    config,
+                    buffer_size,
+                    incoming_rx,
+                    outgoing_tx,
+                    event_log,
+                    recordings,
+                ),
+                cpal::SampleFormat::U16 => Patch::run::<u16>(
+                    &device,
+                    config,
+                    buffer_size,
+                    incoming_rx,
+                    outgoing_tx,
+                    event_log,
+                    recordings,
+                ),
+                cpal::SampleFormat::F32 => Patch::run::<f32>(
+                    &device,
+                    config,
+                    buffer_size,
+                    incoming_rx,
+                    outgoing_tx,
+                    event_log,
+                    recordings,
+                ),
+            }
         })
     }
 }
 
+// Finds an audio host such as "ALSA", "JACK" or "ASIO" by name, or the platform default.
+fn get_host(name: Option<&str>) -> Result<cpal::Host> {
+    let name = match name {
+        Some(name) => name,
+        None => return Ok(cpal::default_host()),
+    };
+    let available = cpal::available_hosts();
+    match available
+        .iter()
+        .find(|id| id.name().eq_ignore_ascii_case(name))
+    {
+        Some(id) => Ok(cpal::host_from_id(*id)?),
+        None => Err(anyhow!(
+            "audio host {} is not available, try one of {:?}",
+            name,
+            available.iter().map(|id| id.name()).collect::<Vec<_>>()
+        )),
+    }
+}
+
+// Picks the device config closest to what was requested. Settings the device can't do fall back
+// to the nearest it can, with a warning, rather than failing to start.
+fn stream_config(
+    device: &cpal::Device,
+    requested: &AudioConfig,
+) -> Result<(SupportedStreamConfig, BufferSize)> {
+    let default = device.default_output_config()?;
+    let config = if requested.sample_rate.is_none() && requested.channels.is_none() {
+        default
+    } else {
+        let sample_rate = requested.sample_rate.unwrap_or(default.sample_rate().0);
+        let channels = requested.channels.unwrap_or(default.channels());
+        let sample_format = default.sample_format();
+        let supported = device.supported_output_configs()?.find(|range| {
+            range.channels() == channels
+                && range.sample_format() == sample_format
+                && range.min_sample_rate().0 <= sample_rate
+                && sample_rate <= range.max_sample_rate().0
+        });
+        match supported {
+            Some(range) => range.with_sample_rate(SampleRate(sample_rate)),
+            None => {
+                eprintln!(
+                    "{} channels at {}Hz is not supported, using {} channels at {}Hz",
+                    channels,
+                    sample_rate,
+                    default.channels(),
+                    default.sample_rate().0
+                );
+                default
+            }
+        }
+    };
+    let buffer_size = match (requested.buffer_size, config.buffer_size()) {
+        (Some(size), SupportedBufferSize::Range { min, max }) => {
+            let clamped = size.max(*min).min(*max);
+            if clamped != size {
+                eprintln!("buffer size {} is not supported, using {}", size, clamped);
+            }
+            BufferSize::Fixed(clamped)
+        }
+        (Some(size), SupportedBufferSize::Unknown) => BufferSize::Fixed(size),
+        (None, _) => BufferSize::Default,
+    };
+    Ok((config, buffer_size))
+}
+
 // fn create_patch(mut configs: HashMap<Uuid, Config>) -> Result<Patch> {
 //     if !configs.contains_key(&ROOT_ID) {
 //         configs.insert(
@@ -0,0 +1,129 @@
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::{fs, path::PathBuf, time::SystemTime};
+
+use crate::types::PatchGraph;
+
+const EXTENSION: &str = "json";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct PatchMetadata {
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PatchInfo {
+    pub name: String,
+    pub metadata: PatchMetadata,
+    pub modified: SystemTime,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PatchFile {
+    #[serde(default)]
+    metadata: PatchMetadata,
+    graph: PatchGraph,
+}
+
+// Whether `name` can be used as a file name inside a directory without pointing anywhere else,
+// since names come from clients.
+pub fn is_valid_name(name: &str) -> bool {
+    !(name.is_empty() || name.starts_with('.') || name.contains(|c| c == '/' || c == '\\'))
+}
+
+// named patches saved as one json file each in a directory
+pub struct PatchLibrary {
+    dir: PathBuf,
+}
+
+impl Default for PatchLibrary {
+    fn default() -> Self {
+        PatchLibrary::new("patches")
+    }
+}
+
+impl PatchLibrary {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        PatchLibrary { dir: dir.into() }
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf> {
+        if !is_valid_name(name) {
+            return Err(anyhow!("{} is not a valid patch name", name));
+        }
+        Ok(self.dir.join(format!("{}.{}", name, EXTENSION)))
+    }
+
+    fn read(&self, name: &str) -> Result<PatchFile> {
+        let path = self.path(name)?;
+        if !path.exists() {
+            return Err(anyhow!("patch {} not found", name));
+        }
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    }
+
+    pub fn list(&self) -> Result<Vec<PatchInfo>> {
+        if !self.dir.exists() {
+            return Ok(vec![]);
+        }
+        let mut patches = vec![];
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(EXTENSION) {
+                continue;
+            }
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) => name.to_owned(),
+                None => continue,
+            };
+            // files that aren't patches are left alone rather than failing the whole listing
+            let file = match self.read(&name) {
+                Ok(file) => file,
+                Err(_) => continue,
+            };
+            patches.push(PatchInfo {
+                name,
+                metadata: file.metadata,
+                modified: fs::metadata(&path)?.modified()?,
+            });
+        }
+        patches.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(patches)
+    }
+
+    pub fn load(&self, name: &str) -> Result<PatchGraph> {
+        Ok(self.read(name)?.graph)
+    }
+
+    // Writes to a temporary file first so a failed save never leaves a truncated patch behind.
+    pub fn save(&self, name: &str, metadata: PatchMetadata, graph: PatchGraph) -> Result<()> {
+        let path = self.path(name)?;
+        fs::create_dir_all(&self.dir)?;
+        let tmp = path.with_extension("tmp");
+        fs::write(
+            &tmp,
+            serde_json::to_string_pretty(&PatchFile { metadata, graph })?,
+        )?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    pub fn delete(&self, name: &str) -> Result<()> {
+        self.read(name)?;
+        fs::remove_file(self.path(name)?)?;
+        Ok(())
+    }
+
+    pub fn rename(&self, from: &str, to: &str) -> Result<()> {
+        self.read(from)?;
+        let to_path = self.path(to)?;
+        if to_path.exists() {
+            return Err(anyhow!("patch {} already exists", to));
+        }
+        fs::rename(self.path(from)?, to_path)?;
+        Ok(())
+    }
+}
@@ -1,25 +1,57 @@
+use anyhow::anyhow;
 use crossbeam_channel::Sender;
 use parking_lot::{Mutex, RwLock};
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, SystemTime},
+};
 use uuid::Uuid;
 
 use crate::{
-    dsp::get_constructors,
-    dsp::schema,
+    automation::Automation,
+    autosave::{Autosave, Autosaver},
+    dsp::{schema, utilities::script::compile_scripts},
+    event_log::{EventLog, PatchEvent},
+    history::{load_ops, touched, History, PatchOp},
+    library::{is_valid_name, PatchInfo, PatchLibrary, PatchMetadata},
     patch::Patch,
+    randomize::randomize,
+    recording::start_recording,
+    render::render_to_file,
+    scope::{create_scope, ScopeReaders},
+    snapshot::{capture, recall, Snapshot},
     types::ModuleSchema,
-    types::{InternalTrack, Keyframe, ModuleState, Param, Track, TrackUpdate},
+    types::{
+        AudioConfig, InternalTrack, Keyframe, ModuleState, OutputRoute, OutputRoutes, Param,
+        PatchGraph, SessionState, Track, TrackUpdate,
+    },
+    validation::{validate, ValidationError},
 };
 
 #[derive(Debug, Clone)]
 pub enum InputMessage {
     Echo(String),
     Schema,
+    GetAudioConfig,
+    GetOutputRoutes,
+    SetOutputRoute(u16, Option<OutputRoute>),
     GetModules,
     GetModule(Uuid),
     CreateModule(String, Uuid),
     UpdateParam(Uuid, String, Param),
     DeleteModule(Uuid),
+    // moves the module's params with a range or options towards random values, by an amount
+    // from 0 to 1
+    RandomizeModule(Uuid, f32),
+    Undo,
+    Redo,
+    GetHistoryDepth,
+    // changes made after the given revision
+    GetEvents(u64),
 
     GetTracks,
     GetTrack(Uuid),
@@ -28,30 +60,154 @@ pub enum InputMessage {
     DeleteTrack(Uuid),
     UpsertKeyframe(Keyframe),
     DeleteKeyframe(Uuid, Uuid),
+    // module, param and the track its changes are recorded into
+    ArmAutomation(Uuid, String, Uuid),
+    DisarmAutomation(Uuid, String),
+
+    CreateScope(Uuid, Uuid, String, u32),
+    DeleteScope(Uuid),
+
+    GetSessionState,
+    SetSessionState(SessionState),
+
+    // recordings and renders are named, and written as .wav files in the recordings directory
+    StartRecording(String),
+    StopRecording,
+
+    Render(String, Duration),
+
+    ListPatches,
+    SavePatch(String, PatchMetadata),
+    LoadPatch(String),
+    DeletePatch(String),
+    RenamePatch(String, String),
+    SetPatchGraph(PatchGraph),
+    ValidatePatchGraph(PatchGraph),
+
+    CaptureSnapshot(String),
+    RecallSnapshot(String, Duration),
+    DeleteSnapshot(String),
+
+    GetRecovery,
+    Recover,
+    DiscardRecovery,
+
+    // CreateModule, UpdateParam and DeleteModule messages applied as one transaction, all or none
+    Batch(Vec<InputMessage>),
+    // handles the message only if the patch is still at the given revision
+    AtRevision(u64, Box<InputMessage>),
+    // the message as sent by the client at the given address, which the event log records along
+    // with any changes it makes
+    FromClient(String, Box<InputMessage>),
+
+    // fades the output out, finishes any recording and stops the patch
+    Shutdown,
 }
 
 #[derive(Debug, Clone)]
 pub enum OutputMessage {
     Echo(String),
     Schema(Vec<ModuleSchema>),
+    AudioConfig(AudioConfig),
+    OutputRoutes(OutputRoutes),
     PatchState(Vec<ModuleState>),
     ModuleState(Uuid, Option<ModuleState>),
     Track(Track),
     CreateModule(String, Uuid),
     CreateTrack(Uuid),
+    CreateScope(Uuid),
+    // a scope that couldn't be created, and why
+    ScopeError(Uuid, String),
+    // the index of the chunk within its frame, so a client can tell where each frame starts and
+    // whether a chunk went missing
+    ScopeData(Uuid, u32, Vec<f32>),
+    SessionState(SessionState),
+    RecordingStarted(String),
+    RecordingStopped(String),
+    RenderFinished(String),
+    PatchList(Vec<PatchInfo>),
+    // empty when the graph is valid
+    Validation(Vec<ValidationError>),
+    Snapshots(Vec<String>),
+    Revision(u64),
+    // steps that can be undone and redone
+    HistoryDepth(usize, usize),
+    Events(Vec<PatchEvent>),
+    // when the autosave left behind by a crash was made, if there is one
+    Recovery(Option<SystemTime>),
+    Conflict(u64),
     Error(String),
 }
 
+// state owned by the message thread that the audio thread never needs to see
+#[derive(Default)]
+pub struct MessageState {
+    pub scope_readers: ScopeReaders,
+    pub history: History,
+    pub library: PatchLibrary,
+    pub snapshots: HashMap<String, Snapshot>,
+    pub revision: u64,
+    pub event_log: EventLog,
+    // the client whose message is being handled, if it came from one
+    pub client: Option<String>,
+    pub automation: Automation,
+    // where recordings and renders are written
+    pub recordings: PathBuf,
+    // the thread writing the current recording
+    pub recording: Option<JoinHandle<()>>,
+    // the thread rendering to a file, one at a time so renders can't pile up
+    pub render: Option<JoinHandle<()>>,
+    pub autosaver: Autosaver,
+    // read once at startup, before new autosaves start replacing it
+    pub recovery: Option<Autosave>,
+}
+
 pub fn handle_message(
     message: InputMessage,
     patch: &Arc<Mutex<Patch>>,
     sender: &Sender<OutputMessage>,
     sample_rate: f32,
+    state: &mut MessageState,
 ) -> anyhow::Result<()> {
     println!("{:?}", message);
     match message {
         InputMessage::Echo(s) => sender.send(OutputMessage::Echo(format!("{}!", s)))?,
         InputMessage::Schema => sender.send(OutputMessage::Schema(schema()))?,
+        InputMessage::GetAudioConfig => {
+            let patch = patch.try_lock_for(Duration::from_millis(10)).unwrap();
+            sender.send(OutputMessage::AudioConfig(AudioConfig {
+                sample_rate: Some(sample_rate as u32),
+                buffer_size: if patch.block_size > 0 {
+                    Some(patch.block_size as u32)
+                } else {
+                    None
+                },
+                channels: Some(patch.channels as u16),
+            }))?
+        }
+        InputMessage::GetOutputRoutes => {
+            let routes = patch
+                .try_lock_for(Duration::from_millis(10))
+                .unwrap()
+                .routes
+                .clone();
+            sender.send(OutputMessage::OutputRoutes(routes))?
+        }
+        InputMessage::SetOutputRoute(channel, route) => {
+            let mut patch = patch.try_lock_for(Duration::from_millis(10)).unwrap();
+            if channel as usize >= patch.channels {
+                sender.send(OutputMessage::Error(format!(
+                    "the device has no output channel {}",
+                    channel
+                )))?
+            } else {
+                match route {
+                    Some(route) => patch.routes.insert(channel, route),
+                    None => patch.routes.remove(&channel),
+                };
+                sender.send(OutputMessage::OutputRoutes(patch.routes.clone()))?
+            }
+        }
         InputMessage::GetModules => {
             sender.send(OutputMessage::PatchState(
                 patch
@@ -73,47 +229,78 @@ pub fn handle_message(
             sender.send(OutputMessage::ModuleState(id, state))?;
         }
         InputMessage::CreateModule(module_type, id) => {
-            let constructors = get_constructors();
-            println!("sample rate {}", sample_rate);
-            if let Some(constructor) = constructors.get(&module_type) {
-                match constructor(&id, sample_rate) {
-                    Ok(module) => {
-                        println!("attempt write");
-                        patch
-                            .try_lock_for(Duration::from_millis(10))
-                            .unwrap()
-                            .sampleables
-                            .insert(id.clone(), module);
-                        println!("written");
-                        sender.send(OutputMessage::CreateModule(module_type, id))?
-                    }
-                    Err(err) => {
-                        println!("{}", err);
-                        sender.send(OutputMessage::Error(format!("an error occured: {}", err)))?;
-                    }
-                };
-            } else {
-                sender.send(OutputMessage::Error(format!(
-                    "{} is not a valid module type",
-                    module_type
-                )))?;
+            let op = PatchOp::CreateModule(module_type.clone(), id);
+            match apply_ops(
+                &mut patch.try_lock_for(Duration::from_millis(10)).unwrap(),
+                vec![op],
+                sample_rate,
+                state,
+                sender,
+            ) {
+                Ok(()) => sender.send(OutputMessage::CreateModule(module_type, id))?,
+                Err(err) => {
+                    sender.send(OutputMessage::Error(format!("an error occured: {}", err)))?
+                }
             }
         }
         InputMessage::UpdateParam(id, param_name, new_param) => {
-            let patch = patch.try_lock_for(Duration::from_millis(10)).unwrap();
-            match patch.sampleables.get(&id) {
-                Some(module) => {
-                    module.update_param(&param_name, &new_param.to_internal_param(&patch))?
-                }
-                None => sender.send(OutputMessage::Error(format!("{} not found", id)))?,
+            let mut ops = vec![PatchOp::UpdateParam(
+                id,
+                param_name.clone(),
+                new_param.clone(),
+                None,
+            )];
+            let result = compile_scripts(&mut ops).and_then(|()| {
+                let mut patch = patch.try_lock_for(Duration::from_millis(10)).unwrap();
+                apply_ops(&mut patch, ops, sample_rate, state, sender)?;
+                state.automation.record(&patch, id, &param_name, &new_param);
+                Ok(())
+            });
+            if let Err(err) = result {
+                sender.send(OutputMessage::Error(format!("an error occured: {}", err)))?;
             }
         }
         InputMessage::DeleteModule(id) => {
-            patch
-                .try_lock_for(Duration::from_millis(10))
-                .unwrap()
-                .sampleables
-                .remove(&id);
+            let op = PatchOp::DeleteModule(id);
+            apply_ops(
+                &mut patch.try_lock_for(Duration::from_millis(10)).unwrap(),
+                vec![op],
+                sample_rate,
+                state,
+                sender,
+            )?;
+        }
+        InputMessage::RandomizeModule(id, amount) => {
+            let mut patch = patch.try_lock_for(Duration::from_millis(10)).unwrap();
+            if let Err(err) = randomize(&patch, id, amount)
+                .and_then(|ops| apply_ops(&mut patch, ops, sample_rate, state, sender))
+            {
+                sender.send(OutputMessage::Error(format!("an error occured: {}", err)))?;
+            }
+        }
+        InputMessage::Undo => {
+            let result = compile_scripts(state.history.next_undo()).and_then(|()| {
+                let mut patch = patch.try_lock_for(Duration::from_millis(10)).unwrap();
+                let ops = state.history.undo(&mut patch, sample_rate)?;
+                publish(&patch, &ops, state, sender)
+            });
+            if let Err(err) = result {
+                sender.send(OutputMessage::Error(format!("an error occured: {}", err)))?;
+            }
+        }
+        InputMessage::Redo => {
+            let result = compile_scripts(state.history.next_redo()).and_then(|()| {
+                let mut patch = patch.try_lock_for(Duration::from_millis(10)).unwrap();
+                let ops = state.history.redo(&mut patch, sample_rate)?;
+                publish(&patch, &ops, state, sender)
+            });
+            if let Err(err) = result {
+                sender.send(OutputMessage::Error(format!("an error occured: {}", err)))?;
+            }
+        }
+        InputMessage::GetHistoryDepth => send_history_depth(&state.history, sender)?,
+        InputMessage::GetEvents(revision) => {
+            sender.send(OutputMessage::Events(state.event_log.since(revision)))?
         }
         InputMessage::GetTracks => {
             for (_, internal_track) in patch
@@ -178,6 +365,373 @@ pub fn handle_message(
                 track.remove_keyframe(id);
             }
         }
+        InputMessage::ArmAutomation(id, param_name, track_id) => {
+            let patch = patch.try_lock_for(Duration::from_millis(10)).unwrap();
+            if let Err(err) = state.automation.arm(&patch, id, param_name, track_id) {
+                sender.send(OutputMessage::Error(format!("an error occured: {}", err)))?;
+            }
+        }
+        InputMessage::DisarmAutomation(id, param_name) => {
+            if let Some(track_id) = state.automation.disarm(id, param_name) {
+                if let Some(ref track) = patch
+                    .try_lock_for(Duration::from_millis(10))
+                    .unwrap()
+                    .tracks
+                    .get(&track_id)
+                {
+                    sender.send(OutputMessage::Track(track.to_track()))?;
+                }
+            }
+        }
+        InputMessage::CreateScope(id, module, port, decimation) => {
+            let mut patch = patch.try_lock_for(Duration::from_millis(10)).unwrap();
+            match create_scope(&patch, &module, port, decimation) {
+                Some((scope, reader)) => {
+                    patch.scopes.insert(id, scope);
+                    state.scope_readers.insert(id, reader);
+                    sender.send(OutputMessage::CreateScope(id))?
+                }
+                None => sender.send(OutputMessage::ScopeError(
+                    id,
+                    format!("{} not found", module),
+                ))?,
+            }
+        }
+        InputMessage::DeleteScope(id) => {
+            patch
+                .try_lock_for(Duration::from_millis(10))
+                .unwrap()
+                .scopes
+                .remove(&id);
+            state.scope_readers.remove(&id);
+        }
+        InputMessage::GetSessionState => {
+            let session_state = patch
+                .try_lock_for(Duration::from_millis(10))
+                .unwrap()
+                .get_session_state();
+            match session_state {
+                Ok(session_state) => sender.send(OutputMessage::SessionState(session_state))?,
+                Err(err) => {
+                    sender.send(OutputMessage::Error(format!("an error occured: {}", err)))?
+                }
+            }
+        }
+        InputMessage::SetSessionState(session_state) => {
+            if let Err(err) = patch
+                .try_lock_for(Duration::from_millis(10))
+                .unwrap()
+                .set_session_state(&session_state)
+            {
+                sender.send(OutputMessage::Error(format!("an error occured: {}", err)))?;
+            }
+        }
+        InputMessage::StartRecording(name) => {
+            let started = recording_path(&state.recordings, &name).and_then(|path| {
+                let (recorder, handle) =
+                    start_recording(path.clone(), sample_rate, sender.clone())?;
+                Ok((path, recorder, handle))
+            });
+            match started {
+                Ok((path, recorder, handle)) => {
+                    // replacing a running recorder punches out of its file
+                    patch
+                        .try_lock_for(Duration::from_millis(10))
+                        .unwrap()
+                        .recorder = Some(recorder);
+                    state.recording = Some(handle);
+                    sender.send(OutputMessage::RecordingStarted(path))?
+                }
+                Err(err) => {
+                    sender.send(OutputMessage::Error(format!("an error occured: {}", err)))?
+                }
+            }
+        }
+        InputMessage::StopRecording => {
+            patch
+                .try_lock_for(Duration::from_millis(10))
+                .unwrap()
+                .recorder = None;
+        }
+        InputMessage::Render(_, _)
+            if state
+                .render
+                .as_ref()
+                .map_or(false, |render| !render.is_finished()) =>
+        {
+            sender.send(OutputMessage::Error(
+                "a render is already running".to_owned(),
+            ))?
+        }
+        InputMessage::Render(name, duration) => {
+            // bounce what the patch looks like now without holding the lock while rendering
+            let graph = patch
+                .try_lock_for(Duration::from_millis(10))
+                .unwrap()
+                .get_graph();
+            let recordings = state.recordings.clone();
+            let sender = sender.clone();
+            state.render = Some(thread::spawn(move || {
+                let rendered = recording_path(&recordings, &name).and_then(|path| {
+                    render_to_file(&graph, duration, sample_rate, &path)?;
+                    Ok(path)
+                });
+                let message = match rendered {
+                    Ok(path) => OutputMessage::RenderFinished(path),
+                    Err(err) => OutputMessage::Error(format!("an error occured: {}", err)),
+                };
+                let _ = sender.send(message);
+            }));
+        }
+        InputMessage::ListPatches => send_patch_list(&state.library, sender)?,
+        InputMessage::SavePatch(name, metadata) => {
+            let graph = patch
+                .try_lock_for(Duration::from_millis(10))
+                .unwrap()
+                .get_graph();
+            match state.library.save(&name, metadata, graph) {
+                Ok(()) => send_patch_list(&state.library, sender)?,
+                Err(err) => {
+                    sender.send(OutputMessage::Error(format!("an error occured: {}", err)))?
+                }
+            }
+        }
+        InputMessage::LoadPatch(name) => {
+            let result = state
+                .library
+                .load(&name)
+                .and_then(|graph| load_graph(patch, &graph, sample_rate, state, sender));
+            match result {
+                Ok(states) => sender.send(OutputMessage::PatchState(states))?,
+                Err(err) => {
+                    sender.send(OutputMessage::Error(format!("an error occured: {}", err)))?
+                }
+            }
+        }
+        InputMessage::SetPatchGraph(graph) => {
+            match load_graph(patch, &graph, sample_rate, state, sender) {
+                Ok(states) => sender.send(OutputMessage::PatchState(states))?,
+                Err(err) => {
+                    sender.send(OutputMessage::Error(format!("an error occured: {}", err)))?
+                }
+            }
+        }
+        InputMessage::ValidatePatchGraph(graph) => {
+            sender.send(OutputMessage::Validation(validate(&graph, sample_rate)))?
+        }
+        InputMessage::DeletePatch(name) => match state.library.delete(&name) {
+            Ok(()) => send_patch_list(&state.library, sender)?,
+            Err(err) => sender.send(OutputMessage::Error(format!("an error occured: {}", err)))?,
+        },
+        InputMessage::RenamePatch(from, to) => match state.library.rename(&from, &to) {
+            Ok(()) => send_patch_list(&state.library, sender)?,
+            Err(err) => sender.send(OutputMessage::Error(format!("an error occured: {}", err)))?,
+        },
+        InputMessage::CaptureSnapshot(name) => {
+            let snapshot = capture(&patch.try_lock_for(Duration::from_millis(10)).unwrap());
+            state.snapshots.insert(name, snapshot);
+            send_snapshots(&state.snapshots, sender)?;
+        }
+        InputMessage::RecallSnapshot(name, duration) => match state.snapshots.get(&name) {
+            Some(snapshot) => {
+                let (mut ops, morph) = recall(
+                    &patch.try_lock_for(Duration::from_millis(10)).unwrap(),
+                    snapshot,
+                    duration,
+                    sample_rate,
+                );
+                // compiled with the patch unlocked, which is safe since only this thread changes it
+                let result = compile_scripts(&mut ops).and_then(|()| {
+                    let mut patch = patch.try_lock_for(Duration::from_millis(10)).unwrap();
+                    apply_ops(&mut patch, ops, sample_rate, state, sender)?;
+                    patch.morph = morph;
+                    Ok(())
+                });
+                if let Err(err) = result {
+                    sender.send(OutputMessage::Error(format!("an error occured: {}", err)))?;
+                }
+            }
+            None => sender.send(OutputMessage::Error(format!("snapshot {} not found", name)))?,
+        },
+        InputMessage::DeleteSnapshot(name) => {
+            state.snapshots.remove(&name);
+            send_snapshots(&state.snapshots, sender)?;
+        }
+        InputMessage::GetRecovery => sender.send(OutputMessage::Recovery(
+            state.recovery.as_ref().map(|recovery| recovery.saved),
+        ))?,
+        InputMessage::Recover => match state.recovery.take() {
+            Some(recovery) => {
+                let result = load_graph(patch, &recovery.graph, sample_rate, state, sender)
+                    .and_then(|states| {
+                        patch
+                            .try_lock_for(Duration::from_millis(10))
+                            .unwrap()
+                            .set_session_state(&recovery.session_state)?;
+                        Ok(states)
+                    });
+                match result {
+                    Ok(states) => sender.send(OutputMessage::PatchState(states))?,
+                    Err(err) => {
+                        sender.send(OutputMessage::Error(format!("an error occured: {}", err)))?
+                    }
+                }
+            }
+            None => sender.send(OutputMessage::Error("nothing to recover".to_owned()))?,
+        },
+        InputMessage::DiscardRecovery => {
+            state.recovery = None;
+            sender.send(OutputMessage::Recovery(None))?
+        }
+        InputMessage::Batch(messages) => {
+            let result = messages
+                .into_iter()
+                .map(|message| match message {
+                    InputMessage::CreateModule(module_type, id) => {
+                        Ok(PatchOp::CreateModule(module_type, id))
+                    }
+                    InputMessage::UpdateParam(id, param_name, new_param) => {
+                        Ok(PatchOp::UpdateParam(id, param_name, new_param, None))
+                    }
+                    InputMessage::DeleteModule(id) => Ok(PatchOp::DeleteModule(id)),
+                    message => Err(anyhow!("{:?} can't be part of a batch", message)),
+                })
+                .collect::<anyhow::Result<Vec<PatchOp>>>()
+                .and_then(|mut ops| {
+                    compile_scripts(&mut ops)?;
+                    apply_ops(
+                        &mut patch.try_lock_for(Duration::from_millis(10)).unwrap(),
+                        ops,
+                        sample_rate,
+                        state,
+                        sender,
+                    )
+                });
+            if let Err(err) = result {
+                sender.send(OutputMessage::Error(format!("an error occured: {}", err)))?;
+            }
+        }
+        InputMessage::AtRevision(revision, message) => {
+            if revision == state.revision {
+                handle_message(*message, patch, sender, sample_rate, state)?;
+            } else {
+                sender.send(OutputMessage::Conflict(state.revision))?;
+            }
+        }
+        InputMessage::FromClient(client, message) => {
+            state.client = Some(client);
+            let result = handle_message(*message, patch, sender, sample_rate, state);
+            state.client = None;
+            result?;
+        }
+        // handled by the patch's run loop
+        InputMessage::Shutdown => {}
     };
     Ok(())
 }
+
+// Where the recording or render called `name` is written. Only a name is taken from the client,
+// so nothing can be written outside the recordings directory.
+fn recording_path(recordings: &Path, name: &str) -> anyhow::Result<String> {
+    if !is_valid_name(name) {
+        return Err(anyhow!("{} is not a valid recording name", name));
+    }
+    fs::create_dir_all(recordings)?;
+    Ok(recordings
+        .join(format!("{}.wav", name))
+        .to_string_lossy()
+        .into_owned())
+}
+
+// Replaces the whole patch with `graph` as one undoable transaction. The graph is validated first
+// so a bad graph is rejected before anything in the running patch changes.
+fn load_graph(
+    patch: &Arc<Mutex<Patch>>,
+    graph: &PatchGraph,
+    sample_rate: f32,
+    state: &mut MessageState,
+    sender: &Sender<OutputMessage>,
+) -> anyhow::Result<Vec<ModuleState>> {
+    let errors = validate(graph, sample_rate);
+    if !errors.is_empty() {
+        let errors: Vec<String> = errors.iter().map(|error| error.to_string()).collect();
+        return Err(anyhow!("invalid patch: {}", errors.join(", ")));
+    }
+    let mut ops = load_ops(
+        &patch.try_lock_for(Duration::from_millis(10)).unwrap(),
+        graph,
+    );
+    // compiled with the patch unlocked, which is safe since only this thread changes it
+    compile_scripts(&mut ops)?;
+    let mut patch = patch.try_lock_for(Duration::from_millis(10)).unwrap();
+    apply_ops(&mut patch, ops, sample_rate, state, sender)?;
+    Ok(patch
+        .sampleables
+        .values()
+        .map(|module| module.get_state())
+        .collect())
+}
+
+// Applies `ops` as one undoable transaction and publishes the change.
+fn apply_ops(
+    patch: &mut Patch,
+    ops: Vec<PatchOp>,
+    sample_rate: f32,
+    state: &mut MessageState,
+    sender: &Sender<OutputMessage>,
+) -> anyhow::Result<()> {
+    state.history.apply(patch, ops.clone(), sample_rate)?;
+    publish(patch, &ops, state, sender)
+}
+
+// Moves to a new revision and sends every client the new state of the modules `ops` changed.
+fn publish(
+    patch: &Patch,
+    ops: &[PatchOp],
+    state: &mut MessageState,
+    sender: &Sender<OutputMessage>,
+) -> anyhow::Result<()> {
+    state.revision += 1;
+    state
+        .event_log
+        .record(state.revision, state.client.clone(), ops);
+    send_module_states(patch, &touched(ops), sender)?;
+    sender.send(OutputMessage::Revision(state.revision))?;
+    send_history_depth(&state.history, sender)
+}
+
+fn send_history_depth(history: &History, sender: &Sender<OutputMessage>) -> anyhow::Result<()> {
+    let (undo, redo) = history.depth();
+    sender.send(OutputMessage::HistoryDepth(undo, redo))?;
+    Ok(())
+}
+
+fn send_module_states(
+    patch: &Patch,
+    ids: &[Uuid],
+    sender: &Sender<OutputMessage>,
+) -> anyhow::Result<()> {
+    for id in ids.iter() {
+        let state = patch.sampleables.get(id).map(|module| module.get_state());
+        sender.send(OutputMessage::ModuleState(*id, state))?;
+    }
+    Ok(())
+}
+
+fn send_patch_list(library: &PatchLibrary, sender: &Sender<OutputMessage>) -> anyhow::Result<()> {
+    match library.list() {
+        Ok(patches) => sender.send(OutputMessage::PatchList(patches))?,
+        Err(err) => sender.send(OutputMessage::Error(format!("an error occured: {}", err)))?,
+    }
+    Ok(())
+}
+
+fn send_snapshots(
+    snapshots: &HashMap<String, Snapshot>,
+    sender: &Sender<OutputMessage>,
+) -> anyhow::Result<()> {
+    let mut names: Vec<String> = snapshots.keys().cloned().collect();
+    names.sort();
+    sender.send(OutputMessage::Snapshots(names))?;
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use std::{
+    cell::Cell,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Instant,
+};
+
+thread_local! {
+    // time spent in modules pulled in by the module currently updating
+    static NESTED_NS: Cell<u64> = Cell::new(0);
+}
+
+// Runs `f` and returns the time spent in it, excluding time spent updating other modules from
+// inside it. Modules update their inputs on demand, so without this every module would also be
+// charged for everything upstream of it.
+pub fn measure<F: FnOnce()>(f: F) -> u64 {
+    let outer = NESTED_NS.with(|nested| nested.replace(0));
+    let start = Instant::now();
+    f();
+    let elapsed = start.elapsed().as_nanos() as u64;
+    let inner = NESTED_NS.with(|nested| nested.replace(outer + elapsed));
+    elapsed.saturating_sub(inner)
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModuleMetrics {
+    // totals for the last completed audio block
+    pub total_ns: u64,
+    pub max_ns: u64,
+    pub samples: u64,
+}
+
+impl ModuleMetrics {
+    pub fn avg_ns(&self) -> u64 {
+        if self.samples > 0 {
+            self.total_ns / self.samples
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct MetricsAccumulator {
+    total_ns: AtomicU64,
+    max_ns: AtomicU64,
+    samples: AtomicU64,
+    last_total_ns: AtomicU64,
+    last_max_ns: AtomicU64,
+    last_samples: AtomicU64,
+}
+
+impl MetricsAccumulator {
+    pub fn record(&self, ns: u64) {
+        self.total_ns.fetch_add(ns, Ordering::Relaxed);
+        self.max_ns.fetch_max(ns, Ordering::Relaxed);
+        self.samples.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // called by the audio thread at the end of every block
+    pub fn commit(&self) {
+        self.last_total_ns
+            .store(self.total_ns.swap(0, Ordering::Relaxed), Ordering::Relaxed);
+        self.last_max_ns
+            .store(self.max_ns.swap(0, Ordering::Relaxed), Ordering::Relaxed);
+        self.last_samples
+            .store(self.samples.swap(0, Ordering::Relaxed), Ordering::Release);
+    }
+
+    pub fn snapshot(&self) -> ModuleMetrics {
+        let samples = self.last_samples.load(Ordering::Acquire);
+        ModuleMetrics {
+            total_ns: self.last_total_ns.load(Ordering::Relaxed),
+            max_ns: self.last_max_ns.load(Ordering::Relaxed),
+            samples,
+        }
+    }
+}
@@ -1,20 +1,50 @@
-use crossbeam_channel::{Receiver, Sender};
+use crossbeam_channel::{Receiver, RecvTimeoutError, Sender};
 use parking_lot::Mutex;
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use rtrb::Producer;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
 
 use crate::{
+    autosave::{Autosave, AUTOSAVE_INTERVAL},
     dsp::get_constructors,
-    message::{handle_message, InputMessage, OutputMessage},
-    types::{SampleableMap, TrackMap, ROOT_ID, ROOT_OUTPUT_PORT},
+    event_log::EventLog,
+    history::{apply, load_ops},
+    message::{handle_message, InputMessage, MessageState, OutputMessage},
+    scope::{drain, ScopeMap, MAX_SCOPE_CHUNK},
+    snapshot::Morph,
+    types::{
+        Config, OutputRoute, OutputRoutes, PatchGraph, SampleableMap, SessionState, TrackMap,
+        ROOT_ID, ROOT_OUTPUT_PORT,
+    },
 };
 use cpal::{
     traits::{DeviceTrait, StreamTrait},
     StreamInstant,
 };
 use uuid::Uuid;
+
+const SCOPE_INTERVAL: Duration = Duration::from_millis(16);
+// long enough to not pop, short enough that quitting still feels immediate
+const FADE_OUT: Duration = Duration::from_millis(100);
+
 pub struct Patch {
     pub sampleables: SampleableMap,
     pub tracks: TrackMap,
+    pub scopes: ScopeMap,
+    pub recorder: Option<Producer<f32>>,
+    pub morph: Option<Morph>,
+    pub routes: OutputRoutes,
+    // frames in the last block the device asked for
+    pub block_size: usize,
+    pub channels: usize,
+    // applied to every output channel, and lowered by `fade_step` each frame once shutting down
+    pub gain: f32,
+    pub fade_step: f32,
 }
 
 impl Patch {
@@ -22,14 +52,100 @@ impl Patch {
         Patch {
             sampleables,
             tracks,
+            scopes: HashMap::new(),
+            recorder: None,
+            morph: None,
+            routes: HashMap::new(),
+            block_size: 0,
+            channels: 0,
+            gain: 1.0,
+            fade_step: 0.0,
+        }
+    }
+
+    // Builds a new patch from `graph`, adding a root if it has none. Track params come out
+    // disconnected since tracks aren't part of the graph.
+    pub fn from_graph(graph: &PatchGraph, sample_rate: f32) -> anyhow::Result<Self> {
+        let mut patch = Patch::new(HashMap::new(), HashMap::new());
+        for op in load_ops(&patch, graph) {
+            apply(&mut patch, &op, sample_rate)?;
+        }
+        Ok(patch)
+    }
+
+    pub fn get_graph(&self) -> PatchGraph {
+        self.sampleables
+            .values()
+            .map(|module| {
+                let state = module.get_state();
+                (
+                    state.id,
+                    Config {
+                        module_type: state.module_type,
+                        params: state.params,
+                    },
+                )
+            })
+            .collect()
+    }
+
+    pub fn get_session_state(&self) -> anyhow::Result<SessionState> {
+        let mut session_state = SessionState::default();
+        for (id, module) in self.sampleables.iter() {
+            session_state
+                .modules
+                .insert(*id, module.get_internal_state()?);
+        }
+        for (id, track) in self.tracks.iter() {
+            session_state
+                .playheads
+                .insert(*id, track.to_track().playhead);
+        }
+        Ok(session_state)
+    }
+
+    // Restores every module and track present in `session_state` while the patch is locked, so the
+    // audio thread never observes a partially restored session. If a module rejects its state, the
+    // modules already restored are put back the way they were, leaving the session unchanged.
+    pub fn set_session_state(&self, session_state: &SessionState) -> anyhow::Result<()> {
+        let mut previous = Vec::new();
+        for (id, state) in session_state.modules.iter() {
+            if let Some(module) = self.sampleables.get(id) {
+                previous.push((module, module.get_internal_state()?));
+                if let Err(err) = module.set_internal_state(state) {
+                    // the failing module may be half set, so it's put back too
+                    let restore_err = previous
+                        .iter()
+                        .rev()
+                        .filter_map(|(module, state)| module.set_internal_state(state).err())
+                        .last();
+                    return match restore_err {
+                        Some(restore_err) => Err(anyhow::anyhow!(
+                            "{}, and restoring the previous session failed: {}",
+                            err,
+                            restore_err
+                        )),
+                        None => Err(err),
+                    };
+                }
+            }
         }
+        for (id, playhead) in session_state.playheads.iter() {
+            if let Some(track) = self.tracks.get(id) {
+                track.seek(*playhead);
+            }
+        }
+        Ok(())
     }
 
     pub fn run<T>(
         device: &cpal::Device,
         config: cpal::SupportedStreamConfig,
+        buffer_size: cpal::BufferSize,
         receiver: Receiver<InputMessage>,
         sender: Sender<OutputMessage>,
+        event_log: Option<PathBuf>,
+        recordings: PathBuf,
     ) -> Result<(), anyhow::Error>
     where
         T: cpal::Sample,
@@ -38,6 +154,11 @@ impl Patch {
         let patch = Arc::new(Mutex::new(Patch::new(HashMap::new(), HashMap::new())));
         let channels = config.channels() as usize;
         println!("{} {}", sample_rate, channels);
+        let sample_format = config.sample_format();
+        let mut stream_config: cpal::StreamConfig = config.into();
+        stream_config.buffer_size = buffer_size;
+
+        patch.lock().channels = channels;
 
         let err_fn = |err| eprintln!("error: {}", err);
         patch.clone().lock().sampleables.insert(
@@ -48,9 +169,9 @@ impl Patch {
         let patch_clone = patch.clone();
 
         let mut last_instant: Option<StreamInstant> = None;
-        let stream = match config.sample_format() {
+        let stream = match sample_format {
             cpal::SampleFormat::F32 => device.build_output_stream(
-                &config.into(),
+                &stream_config,
                 move |data, info: &_| {
                     let new_instant = info.timestamp().callback;
 
@@ -66,7 +187,7 @@ impl Patch {
                 err_fn,
             )?,
             cpal::SampleFormat::I16 => device.build_output_stream(
-                &config.into(),
+                &stream_config,
                 move |data, info: &_| {
                     let new_instant = info.timestamp().callback;
 
@@ -82,7 +203,7 @@ impl Patch {
                 err_fn,
             )?,
             cpal::SampleFormat::U16 => device.build_output_stream(
-                &config.into(),
+                &stream_config,
                 move |data, info: &_| {
                     let new_instant = info.timestamp().callback;
 
@@ -101,23 +222,88 @@ impl Patch {
 
         stream.play()?;
 
-        for message in receiver {
-            handle_message(message, &patch, &sender, sample_rate)?;
+        let mut state = MessageState {
+            event_log: EventLog::new(event_log),
+            recordings,
+            ..MessageState::default()
+        };
+        state.recovery = state.autosaver.latest();
+        if let Some(ref recovery) = state.recovery {
+            sender.send(OutputMessage::Recovery(Some(recovery.saved)))?;
+        }
+        let mut last_scope_send = Instant::now();
+        let mut last_autosave = Instant::now();
+        loop {
+            match receiver.recv_timeout(SCOPE_INTERVAL) {
+                Ok(InputMessage::Shutdown) => break,
+                Ok(message) => handle_message(message, &patch, &sender, sample_rate, &mut state)?,
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => break,
+            }
+            if last_scope_send.elapsed() >= SCOPE_INTERVAL {
+                last_scope_send = Instant::now();
+                for (id, consumer) in state.scope_readers.iter_mut() {
+                    let samples = drain(consumer);
+                    for (index, chunk) in samples.chunks(MAX_SCOPE_CHUNK).enumerate() {
+                        sender.send(OutputMessage::ScopeData(*id, index as u32, chunk.to_vec()))?;
+                    }
+                }
+            }
+            // if the patch is busy the autosave is tried again on the next pass
+            if last_autosave.elapsed() >= AUTOSAVE_INTERVAL {
+                if let Some(locked) = patch.try_lock_for(Duration::from_millis(10)) {
+                    last_autosave = Instant::now();
+                    let autosave = Autosave::capture(&locked);
+                    drop(locked);
+                    if let Err(err) = autosave.and_then(|autosave| state.autosaver.save(&autosave))
+                    {
+                        eprintln!("error: autosave failed: {}", err);
+                    }
+                }
+            }
+        }
+        fade_out(&patch, sample_rate);
+        drop(stream);
+        patch.lock().recorder = None;
+        if let Some(recording) = state.recording.take() {
+            let _ = recording.join();
+        }
+        state.autosaver.clear()?;
+        // a crash that was never recovered or discarded is still offered on the next start
+        if let Some(recovery) = state.recovery.take() {
+            state.autosaver.save(&recovery)?;
         }
         Ok(())
     }
 }
 
+// Ramps the output down to silence and waits until the device has played it.
+fn fade_out(patch: &Arc<Mutex<Patch>>, sample_rate: f32) {
+    let block = {
+        let mut patch = patch.lock();
+        patch.fade_step = 1.0 / (FADE_OUT.as_secs_f32() * sample_rate);
+        Duration::from_secs_f32(patch.block_size as f32 / sample_rate)
+    };
+    thread::sleep(FADE_OUT + block * 2);
+}
+
 fn write_data<T>(output: &mut [T], channels: usize, patch: &mut Patch, delta: &Duration)
 where
     T: cpal::Sample,
 {
+    patch.block_size = output.len() / channels;
     for frame in output.chunks_mut(channels) {
-        let value = cpal::Sample::from::<f32>(&process_frame(patch, delta));
-        for sample in frame.iter_mut() {
-            *sample = value;
+        let root = process_frame(patch, delta);
+        patch.gain = (patch.gain - patch.fade_step).max(0.0);
+        for (channel, sample) in frame.iter_mut().enumerate() {
+            let value = match patch.routes.get(&(channel as u16)) {
+                Some(route) => get_route_output(&patch.sampleables, route),
+                None => root,
+            };
+            *sample = cpal::Sample::from::<f32>(&(value * patch.gain));
         }
     }
+    commit_metrics(&patch.sampleables);
 }
 
 fn update_tracks(tracks: &mut TrackMap, delta: &Duration) {
@@ -138,6 +324,18 @@ fn tick_sampleables(sampleables: &mut SampleableMap) {
     }
 }
 
+fn tick_scopes(scopes: &mut ScopeMap) {
+    for (_, scope) in scopes {
+        scope.tick();
+    }
+}
+
+fn commit_metrics(sampleables: &SampleableMap) {
+    for (_, module) in sampleables {
+        module.commit_metrics();
+    }
+}
+
 fn get_patch_output(sampleables: &SampleableMap) -> f32 {
     if let Some(root) = sampleables.get(&*ROOT_ID) {
         return root.get_sample(&*ROOT_OUTPUT_PORT).unwrap_or_default();
@@ -146,13 +344,34 @@ fn get_patch_output(sampleables: &SampleableMap) -> f32 {
     }
 }
 
-fn process_frame(patch: &mut Patch, delta: &Duration) -> f32 {
+fn get_route_output(sampleables: &SampleableMap, route: &OutputRoute) -> f32 {
+    match sampleables.get(&route.module) {
+        Some(module) => module.get_sample(&route.port).unwrap_or_default() / 5.0,
+        None => 0.0,
+    }
+}
+
+pub(crate) fn process_frame(patch: &mut Patch, delta: &Duration) -> f32 {
     let Patch {
         ref mut sampleables,
         ref mut tracks,
+        ref mut scopes,
+        ref mut recorder,
+        ref mut morph,
+        ..
     } = patch;
     update_tracks(tracks, delta);
+    if let Some(running) = morph {
+        if !running.tick() {
+            *morph = None;
+        }
+    }
     update_sampleables(sampleables);
+    tick_scopes(scopes);
     tick_sampleables(sampleables);
-    get_patch_output(sampleables) / 5.0
+    let output = get_patch_output(sampleables) / 5.0;
+    if let Some(recorder) = recorder {
+        let _ = recorder.push(output);
+    }
+    output
 }
@@ -0,0 +1,63 @@
+use anyhow::{anyhow, Result};
+use uuid::Uuid;
+
+use crate::{
+    dsp::{schema, utils::Rng},
+    history::PatchOp,
+    patch::Patch,
+    types::{Param, PortSchema},
+};
+
+// The ops that move every param of module `id` that has a range towards a value picked uniformly
+// from that range, and change every enum param to a random option, `amount` of the way from 0 to
+// 1. At 1 the current values don't matter, so this also works on a freshly created module.
+// Triggers and gates are left alone so randomizing never fires them, as are params patched with a
+// cable or track, notes, other text and params without a range.
+pub fn randomize(patch: &Patch, id: Uuid, amount: f32) -> Result<Vec<PatchOp>> {
+    let module = match patch.sampleables.get(&id) {
+        Some(module) => module,
+        None => return Err(anyhow!("{} not found", id)),
+    };
+    let state = module.get_state();
+    let ports: &[PortSchema] = schema()
+        .into_iter()
+        .find(|schema| schema.name == state.module_type)
+        .map_or(&[], |schema| schema.params);
+    let amount = amount.max(0.0).min(1.0);
+    let mut rng = Rng::default();
+    Ok(ports
+        .iter()
+        .filter(|port| port.kind.is_none())
+        .filter_map(|port| {
+            let current = state
+                .params
+                .get(port.name)
+                .cloned()
+                .unwrap_or(Param::Disconnected);
+            let param = match (current, port.range, port.options) {
+                (Param::Cable { .. }, _, _) | (Param::Track { .. }, _, _) => return None,
+                (_, _, Some(options)) if !options.is_empty() => {
+                    if rng.next_f32() >= amount {
+                        return None;
+                    }
+                    let index = (rng.next_f32() * options.len() as f32) as usize;
+                    Param::Text {
+                        value: options[index.min(options.len() - 1)].name.to_owned(),
+                    }
+                }
+                (Param::Value { value }, Some((min, max)), _) if amount > 0.0 => {
+                    let target = min + rng.next_f32() * (max - min);
+                    Param::Value {
+                        value: value + (target - value) * amount,
+                    }
+                }
+                // with nothing to move from, it can only jump straight to the random value
+                (Param::Disconnected, Some((min, max)), _) if amount > 0.0 => Param::Value {
+                    value: min + rng.next_f32() * (max - min),
+                },
+                _ => return None,
+            };
+            Some(PatchOp::UpdateParam(id, port.name.to_owned(), param, None))
+        })
+        .collect())
+}
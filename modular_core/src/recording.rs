@@ -0,0 +1,62 @@
+use crossbeam_channel::Sender;
+use hound::{SampleFormat, WavSpec, WavWriter};
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::{
+    fs::File,
+    io::BufWriter,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use crate::message::OutputMessage;
+
+// several seconds of audio, so a slow disk never makes the audio thread drop samples
+const RECORDING_CAPACITY: usize = 1 << 18;
+const WRITE_INTERVAL: Duration = Duration::from_millis(10);
+
+pub fn wav_spec(sample_rate: f32) -> WavSpec {
+    WavSpec {
+        channels: 1,
+        sample_rate: sample_rate as u32,
+        bits_per_sample: 32,
+        sample_format: SampleFormat::Float,
+    }
+}
+
+// Creates the file and spawns the thread that writes it. Recording stops once the returned
+// producer is dropped and everything pushed to it has been written, which is when the thread
+// finishes.
+pub fn start_recording(
+    path: String,
+    sample_rate: f32,
+    sender: Sender<OutputMessage>,
+) -> anyhow::Result<(Producer<f32>, JoinHandle<()>)> {
+    let writer = WavWriter::create(&path, wav_spec(sample_rate))?;
+    let (producer, consumer) = RingBuffer::new(RECORDING_CAPACITY);
+    let handle = thread::spawn(move || {
+        let message = match write(writer, consumer) {
+            Ok(()) => OutputMessage::RecordingStopped(path),
+            Err(err) => OutputMessage::Error(format!("an error occured: {}", err)),
+        };
+        let _ = sender.send(message);
+    });
+    Ok((producer, handle))
+}
+
+fn write(
+    mut writer: WavWriter<BufWriter<File>>,
+    mut consumer: Consumer<f32>,
+) -> anyhow::Result<()> {
+    loop {
+        let abandoned = consumer.is_abandoned();
+        while let Ok(sample) = consumer.pop() {
+            writer.write_sample(sample)?;
+        }
+        if abandoned {
+            break;
+        }
+        thread::sleep(WRITE_INTERVAL);
+    }
+    writer.finalize()?;
+    Ok(())
+}
@@ -0,0 +1,38 @@
+use anyhow::anyhow;
+use hound::WavWriter;
+use std::time::Duration;
+
+use crate::{
+    patch::{process_frame, Patch},
+    recording::wav_spec,
+    types::PatchGraph,
+};
+
+// the longest render allowed, about 660MB of 48kHz float samples
+pub const MAX_RENDER_LENGTH: Duration = Duration::from_secs(60 * 60);
+
+// Renders `graph` from a freshly constructed patch as fast as possible, without an audio device,
+// writing each sample as it's made.
+pub fn render_to_file(
+    graph: &PatchGraph,
+    duration: Duration,
+    sample_rate: f32,
+    path: &str,
+) -> anyhow::Result<()> {
+    if duration > MAX_RENDER_LENGTH {
+        return Err(anyhow!(
+            "{}s is longer than the longest render, {}s",
+            duration.as_secs_f32(),
+            MAX_RENDER_LENGTH.as_secs()
+        ));
+    }
+    let mut patch = Patch::from_graph(graph, sample_rate)?;
+    let delta = Duration::from_secs_f64(1.0 / sample_rate as f64);
+    let frames = (duration.as_secs_f64() * sample_rate as f64) as usize;
+    let mut writer = WavWriter::create(path, wav_spec(sample_rate))?;
+    for _ in 0..frames {
+        writer.write_sample(process_frame(&mut patch, &delta))?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
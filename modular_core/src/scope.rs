@@ -0,0 +1,77 @@
+use rtrb::{Consumer, Producer, RingBuffer};
+use std::{
+    collections::HashMap,
+    sync::{self, Arc},
+};
+use uuid::Uuid;
+
+use crate::{patch::Patch, types::Sampleable};
+
+// roughly 90ms of audio at 44.1kHz without decimation, enough headroom between reads
+pub const SCOPE_CAPACITY: usize = 4096;
+
+pub struct InternalScope {
+    module: sync::Weak<Box<dyn Sampleable>>,
+    port: String,
+    decimation: u32,
+    counter: u32,
+    producer: Producer<f32>,
+}
+
+impl InternalScope {
+    pub fn tick(&mut self) {
+        self.counter += 1;
+        if self.counter < self.decimation {
+            return;
+        }
+        self.counter = 0;
+        let sample = match self.module.upgrade() {
+            Some(module) => module.get_sample(&self.port).unwrap_or_default(),
+            None => 0.0,
+        };
+        // a reader that has fallen behind loses the newest samples rather than blocking audio
+        let _ = self.producer.push(sample);
+    }
+}
+
+pub type ScopeMap = HashMap<Uuid, InternalScope>;
+
+pub type ScopeReaders = HashMap<Uuid, Consumer<f32>>;
+
+pub fn create_scope(
+    patch: &Patch,
+    module: &Uuid,
+    port: String,
+    decimation: u32,
+) -> Option<(InternalScope, Consumer<f32>)> {
+    let module = patch.sampleables.get(module)?;
+    let (producer, consumer) = RingBuffer::new(SCOPE_CAPACITY);
+    Some((
+        InternalScope {
+            module: Arc::downgrade(module),
+            port,
+            decimation: decimation.max(1),
+            counter: 0,
+            producer,
+        },
+        consumer,
+    ))
+}
+
+// Most that goes out in one frame. A reader that has fallen behind skips its oldest samples to
+// catch up instead of sending ever larger packets to a client that is already struggling.
+pub const MAX_SCOPE_FRAME: usize = 2048;
+// Most samples in one ScopeData message. A frame is sent as several of these so that each one
+// fits in a single UDP packet without being fragmented on its way over the network.
+pub const MAX_SCOPE_CHUNK: usize = 256;
+
+pub fn drain(consumer: &mut Consumer<f32>) -> Vec<f32> {
+    let mut samples = Vec::with_capacity(consumer.slots());
+    while let Ok(sample) = consumer.pop() {
+        samples.push(sample);
+    }
+    if samples.len() > MAX_SCOPE_FRAME {
+        samples.drain(..samples.len() - MAX_SCOPE_FRAME);
+    }
+    samples
+}
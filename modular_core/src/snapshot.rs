@@ -0,0 +1,129 @@
+use std::{
+    collections::HashMap,
+    sync::{self, Arc},
+    time::Duration,
+};
+use uuid::Uuid;
+
+use crate::{
+    history::PatchOp,
+    patch::Patch,
+    types::{InternalParam, Param, Sampleable},
+};
+
+// param values by module, leaving out cables and tracks since those are part of the patch's
+// structure rather than its settings
+pub type Snapshot = HashMap<Uuid, HashMap<String, Param>>;
+
+pub fn capture(patch: &Patch) -> Snapshot {
+    patch
+        .sampleables
+        .iter()
+        .map(|(id, module)| {
+            let params = module
+                .get_state()
+                .params
+                .into_iter()
+                .filter(|(_, param)| match param {
+                    Param::Value { .. } | Param::Note { .. } | Param::Text { .. } => true,
+                    _ => false,
+                })
+                .collect();
+            (*id, params)
+        })
+        .collect()
+}
+
+// the longest a recall can take to morph to a snapshot
+pub const MAX_MORPH_LENGTH: Duration = Duration::from_secs(60 * 60);
+
+// samples between the morph's steps, coarse enough to keep setting params off the audio thread's
+// per-sample path
+const MORPH_INTERVAL: u32 = 64;
+
+struct MorphParam {
+    module: sync::Weak<Box<dyn Sampleable>>,
+    param_name: String,
+    from: f32,
+    to: f32,
+}
+
+// Moves value params from where they were towards a recalled snapshot, one step every
+// MORPH_INTERVAL samples.
+pub struct Morph {
+    params: Vec<MorphParam>,
+    length: u32,
+    elapsed: u32,
+}
+
+impl Morph {
+    // returns false once the morph has reached the snapshot
+    pub fn tick(&mut self) -> bool {
+        self.elapsed += 1;
+        // steps on the first sample too, since recalling has already set the params to the
+        // snapshot and they'd otherwise sit there until the first step
+        let finished = self.elapsed >= self.length;
+        if self.elapsed % MORPH_INTERVAL != 1 && !finished {
+            return true;
+        }
+        let t = (self.elapsed as f32 / self.length as f32).min(1.0);
+        for param in self.params.iter() {
+            if let Some(module) = param.module.upgrade() {
+                let value = param.from + (param.to - param.from) * t;
+                let _ = module.update_param(&param.param_name, &InternalParam::Value { value });
+            }
+        }
+        !finished
+    }
+}
+
+// The ops that set every param in `snapshot` that can still be set without changing the patch's
+// structure, plus a morph that glides value params there over `duration`. Params of modules that
+// no longer exist or that are now patched with a cable or track are skipped.
+pub fn recall(
+    patch: &Patch,
+    snapshot: &Snapshot,
+    duration: Duration,
+    sample_rate: f32,
+) -> (Vec<PatchOp>, Option<Morph>) {
+    let mut ops = vec![];
+    let mut morph_params = vec![];
+    for (id, params) in snapshot.iter() {
+        let module = match patch.sampleables.get(id) {
+            Some(module) => module,
+            None => continue,
+        };
+        let current = module.get_state().params;
+        for (param_name, param) in params.iter() {
+            match (current.get(param_name), param) {
+                (Some(Param::Cable { .. }), _) | (Some(Param::Track { .. }), _) => continue,
+                (Some(Param::Value { value: from }), Param::Value { value: to }) => {
+                    morph_params.push(MorphParam {
+                        module: Arc::downgrade(module),
+                        param_name: param_name.clone(),
+                        from: *from,
+                        to: *to,
+                    });
+                }
+                _ => {}
+            }
+            ops.push(PatchOp::UpdateParam(
+                *id,
+                param_name.clone(),
+                param.clone(),
+                None,
+            ));
+        }
+    }
+    let length = (duration.min(MAX_MORPH_LENGTH).as_secs_f32() * sample_rate) as u32;
+    let morph = if length > 0 && !morph_params.is_empty() {
+        Some(Morph {
+            params: morph_params,
+            length,
+            elapsed: 0,
+        })
+    } else {
+        None
+    };
+    (ops, morph)
+}
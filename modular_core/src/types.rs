@@ -1,15 +1,17 @@
 use anyhow::Result;
 use parking_lot::{Mutex, RwLock};
+use rhai::AST;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::time::Duration;
 use std::{
     collections::HashMap,
+    fmt,
     sync::{self, Arc},
 };
 use uuid::Uuid;
 
-use crate::patch::Patch;
+use crate::{metrics::ModuleMetrics, patch::Patch};
 
 lazy_static! {
     pub static ref ROOT_ID: Uuid = Uuid::nil();
@@ -24,6 +26,8 @@ pub trait Params {
         new_param: &InternalParam,
         module_name: &str,
     ) -> Result<()>;
+    // called once on a newly constructed module, before it's first updated
+    fn init(&mut self) {}
     fn get_schema() -> &'static [PortSchema];
 }
 
@@ -33,6 +37,10 @@ pub trait Sampleable: Send + Sync {
     fn update(&self) -> ();
     fn get_sample(&self, port: &String) -> Result<f32>;
     fn get_state(&self) -> ModuleState;
+    fn get_internal_state(&self) -> Result<Value>;
+    fn set_internal_state(&self, state: &Value) -> Result<()>;
+    fn commit_metrics(&self) -> ();
+    fn get_metrics(&self) -> ModuleMetrics;
     fn update_param(&self, param_name: &String, new_param: &InternalParam) -> Result<()>;
 }
 
@@ -41,14 +49,89 @@ pub trait Module {
     fn get_schema() -> ModuleSchema;
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct Config {
     pub module_type: String,
-    pub params: Value,
+    pub params: HashMap<String, Param>,
 }
 
+// everything needed to rebuild a patch's modules and cables
+pub type PatchGraph = HashMap<Uuid, Config>;
+
 pub type SampleableMap = HashMap<Uuid, Arc<Box<dyn Sampleable>>>;
 
+// An InternalParam whose value glides towards changes rather than jumping, for params that would
+// click. #[param(..., smooth = "10ms")] sets `time` in seconds when the module is constructed.
+// Only values set directly glide, cables and tracks are followed as they are so that modulation
+// isn't filtered.
+#[derive(Default)]
+pub struct SmoothedParam {
+    pub param: InternalParam,
+    pub time: f32,
+    value: Option<f32>,
+}
+
+impl SmoothedParam {
+    // advances the glide by one sample
+    pub fn next_value_or(&mut self, default: f32, sample_rate: f32) -> f32 {
+        let target = self.param.get_value_or(default);
+        let value = match (&self.param, self.value) {
+            (InternalParam::Value { .. }, Some(value)) if self.time > 0.0 => {
+                value + (target - value) * (1.0 - (-1.0 / (self.time * sample_rate)).exp())
+            }
+            _ => target,
+        };
+        self.value = Some(value);
+        value
+    }
+
+    pub fn next_value(&mut self, sample_rate: f32) -> f32 {
+        self.next_value_or(0.0, sample_rate)
+    }
+}
+
+pub const GATE_THRESHOLD: f32 = 2.5;
+
+// An InternalParam read as high or low, that remembers its last state to find rising edges. The
+// field type for params marked #[param(..., trigger)] or #[param(..., gate)].
+#[derive(Default)]
+pub struct GateParam {
+    pub param: InternalParam,
+    high: bool,
+}
+
+impl GateParam {
+    pub fn is_high(&self) -> bool {
+        self.param.get_value() > GATE_THRESHOLD
+    }
+
+    // true on the sample the param goes high, so call it once per sample
+    pub fn rising(&mut self) -> bool {
+        let high = self.is_high();
+        let rising = high && !self.high;
+        self.high = high;
+        rising
+    }
+}
+
+// A script compiled on the message thread, carried along with its code so setting it while the
+// patch is locked never has to wait on the compiler.
+#[derive(Clone)]
+pub struct CompiledScript(pub Arc<AST>);
+
+impl fmt::Debug for CompiledScript {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CompiledScript")
+    }
+}
+
+// always compiled from the code it travels with, so comparing that code is enough
+impl PartialEq for CompiledScript {
+    fn eq(&self, _: &Self) -> bool {
+        true
+    }
+}
+
 #[derive(Clone)]
 pub enum InternalParam {
     Value {
@@ -57,6 +140,10 @@ pub enum InternalParam {
     Note {
         value: u8,
     },
+    Text {
+        value: String,
+        compiled: Option<CompiledScript>,
+    },
     Cable {
         module: sync::Weak<Box<dyn Sampleable>>,
         port: String,
@@ -76,6 +163,10 @@ impl PartialEq for InternalParam {
             (InternalParam::Note { value: value1 }, InternalParam::Note { value: value2 }) => {
                 *value1 == *value2
             }
+            (
+                InternalParam::Text { value: value1, .. },
+                InternalParam::Text { value: value2, .. },
+            ) => *value1 == *value2,
             (
                 InternalParam::Cable {
                     module: module1,
@@ -104,6 +195,9 @@ impl InternalParam {
         match self {
             InternalParam::Value { value } => Param::Value { value: *value },
             InternalParam::Note { value } => Param::Note { value: *value },
+            InternalParam::Text { value, .. } => Param::Text {
+                value: value.clone(),
+            },
             InternalParam::Cable { module, port } => match module.upgrade() {
                 Some(module) => Param::Cable {
                     module: module.get_id(),
@@ -128,6 +222,7 @@ impl InternalParam {
         match self {
             InternalParam::Value { value } => Some(*value),
             InternalParam::Note { value } => Some((*value as f32 - 21.0) / 12.0),
+            InternalParam::Text { .. } => None,
             InternalParam::Cable { module, port } => match module.upgrade() {
                 Some(module) => match module.get_sample(port) {
                     Ok(sample) => Some(sample),
@@ -158,6 +253,7 @@ impl Default for InternalParam {
 pub enum Param {
     Value { value: f32 },
     Note { value: u8 },
+    Text { value: String },
     Cable { module: Uuid, port: String },
     Track { track: Uuid },
     Disconnected,
@@ -168,6 +264,10 @@ impl Param {
         match self {
             Param::Value { value } => InternalParam::Value { value: *value },
             Param::Note { value } => InternalParam::Note { value: *value },
+            Param::Text { value } => InternalParam::Text {
+                value: value.clone(),
+                compiled: None,
+            },
             Param::Cable { module, port } => match patch.sampleables.get(module) {
                 Some(module) => InternalParam::Cable {
                     module: Arc::downgrade(module),
@@ -487,13 +587,64 @@ pub struct TrackUpdate {
     play_mode: Option<Playmode>,
 }
 
-#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Serialize, Debug, Clone, Copy, PartialOrd, PartialEq)]
 pub struct PortSchema {
     pub name: &'static str,
     pub description: &'static str,
+    // a label for UIs to show instead of the name
+    pub display: Option<&'static str>,
+    pub unit: Option<&'static str>,
+    // values outside of the range are rejected, the step is a hint for UI controls
+    pub range: Option<(f32, f32)>,
+    pub step: Option<f32>,
+    // the only text values an enum param takes
+    pub options: Option<&'static [ParamOption]>,
+    // related params share a group, for UIs to show them together
+    pub group: Option<&'static str>,
+    pub kind: Option<PortKind>,
 }
 
-#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+impl PortSchema {
+    // for filling in everything but the name and description, `..PortSchema::DEFAULT`
+    pub const DEFAULT: PortSchema = PortSchema {
+        name: "",
+        description: "",
+        display: None,
+        unit: None,
+        range: None,
+        step: None,
+        options: None,
+        group: None,
+        kind: None,
+    };
+}
+
+// how a param is read, for params that are more than a value
+#[derive(Serialize, Debug, Clone, Copy, PartialOrd, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum PortKind {
+    // only the moment it goes high matters
+    Trigger,
+    // matters for as long as it's high
+    Gate,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialOrd, PartialEq)]
+pub struct ParamOption {
+    pub name: &'static str,
+    pub description: &'static str,
+}
+
+// Implemented by #[derive(ParamEnum)] for enums used as param fields in place of an
+// InternalParam. Each variant is an option named after it in kebab case and described by its doc
+// comment.
+pub trait ParamEnum: Sized + Default {
+    const OPTIONS: &'static [ParamOption];
+    fn from_name(name: &str) -> Option<Self>;
+    fn name(&self) -> &'static str;
+}
+
+#[derive(Debug, Clone, Copy, PartialOrd, PartialEq)]
 pub struct ModuleSchema {
     pub name: &'static str,
     pub description: &'static str,
@@ -506,6 +657,31 @@ pub struct ModuleState {
     pub id: Uuid,
     pub module_type: String,
     pub params: HashMap<String, Param>,
+    pub metrics: ModuleMetrics,
+}
+
+// requested audio settings, left to the device's defaults when None, or the ones actually in use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AudioConfig {
+    pub sample_rate: Option<u32>,
+    pub buffer_size: Option<u32>,
+    pub channels: Option<u16>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OutputRoute {
+    pub module: Uuid,
+    pub port: String,
+}
+
+// hardware output channel to the module output it plays, channels without a route play the root
+pub type OutputRoutes = HashMap<u16, OutputRoute>;
+
+// everything a patch accumulates while running that isn't in its params, for resuming a session
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct SessionState {
+    pub modules: HashMap<Uuid, Value>,
+    pub playheads: HashMap<Uuid, Duration>,
 }
 
 pub type SampleableConstructor = Box<dyn Fn(&Uuid, f32) -> Result<Arc<Box<dyn Sampleable>>>>;
@@ -0,0 +1,98 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::HashMap, fmt};
+use uuid::Uuid;
+
+use crate::{
+    dsp::{get_constructors, schema},
+    patch::Patch,
+    types::{Param, PatchGraph, ROOT_ID},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    pub module: Uuid,
+    pub param: Option<String>,
+    pub message: String,
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.param {
+            Some(ref param) => write!(f, "{} {}: {}", self.module, param, self.message),
+            None => write!(f, "{}: {}", self.module, self.message),
+        }
+    }
+}
+
+// Checks everything that would stop `graph` from loading as written and reports all of it at
+// once, without touching the running patch.
+pub fn validate(graph: &PatchGraph, sample_rate: f32) -> Vec<ValidationError> {
+    let constructors = get_constructors();
+    let schemas: HashMap<&str, _> = schema()
+        .into_iter()
+        .map(|schema| (schema.name, schema))
+        .collect();
+    let mut errors = vec![];
+    let mut patch = Patch::new(HashMap::new(), HashMap::new());
+
+    for (id, config) in graph.iter() {
+        let error = match constructors.get(&config.module_type) {
+            Some(constructor) => match constructor(id, sample_rate) {
+                Ok(module) => {
+                    patch.sampleables.insert(*id, module);
+                    continue;
+                }
+                Err(err) => err.to_string(),
+            },
+            None => format!("{} is not a valid module type", config.module_type),
+        };
+        errors.push(ValidationError {
+            module: *id,
+            param: None,
+            message: error,
+        });
+    }
+
+    for (id, config) in graph.iter() {
+        let module = match patch.sampleables.get(id) {
+            Some(module) => module,
+            None => continue,
+        };
+        for (param_name, param) in config.params.iter() {
+            let mut error = None;
+            if let Param::Cable {
+                module: source,
+                port,
+            } = param
+            {
+                // a graph without a root gets a signal module as its root when it's loaded
+                let source_type = match graph.get(source) {
+                    Some(source_config) => Some(source_config.module_type.as_str()),
+                    None if *source == *ROOT_ID => Some("signal"),
+                    None => None,
+                };
+                match source_type.map(|source_type| schemas.get(source_type)) {
+                    None => error = Some(format!("{} is not in the patch", source)),
+                    Some(Some(schema)) if !schema.outputs.iter().any(|o| o.name == port) => {
+                        error = Some(format!("{} has no output {}", source, port))
+                    }
+                    _ => {}
+                }
+            }
+            if error.is_none() {
+                if let Err(err) = module.update_param(param_name, &param.to_internal_param(&patch))
+                {
+                    error = Some(err.to_string());
+                }
+            }
+            if let Some(message) = error {
+                errors.push(ValidationError {
+                    module: *id,
+                    param: Some(param_name.clone()),
+                    message,
+                });
+            }
+        }
+    }
+    errors
+}
@@ -5,13 +5,13 @@ extern crate proc_macro;
 
 use convert_case::{Case, Casing};
 use proc_macro::TokenStream;
-use proc_macro2::{Ident, Span, TokenStream as TokenStream2};
+use proc_macro2::{Ident, Literal, Span, TokenStream as TokenStream2};
 use quote::{format_ident, quote, quote_spanned};
 use syn::{
-    parse::Parser, punctuated::Punctuated, spanned::Spanned, Attribute, Field, FieldsNamed, LitStr,
-    Token,
+    parse::Parser, punctuated::Punctuated, spanned::Spanned, Attribute, Field, FieldsNamed, Lit,
+    LitStr, Meta, NestedMeta, Token,
 };
-use syn::{Data, DeriveInput, Fields};
+use syn::{Data, DeriveInput, Fields, Type};
 
 #[proc_macro_derive(Params, attributes(name, description, param))]
 pub fn params_macro_derive(input: TokenStream) -> TokenStream {
@@ -49,23 +49,196 @@ fn unwrap_attr(attrs: &Vec<Attribute>, ident: &str) -> Option<TokenStream2> {
         })
 }
 
-fn unwrap_name_description(
-    attrs: &Vec<Attribute>,
-    ident: &str,
-) -> (Option<LitStr>, Option<LitStr>) {
-    let attr = unwrap_attr(attrs, ident)
+fn parse_attr(attrs: &Vec<Attribute>, ident: &str) -> Punctuated<NestedMeta, Token![,]> {
+    unwrap_attr(attrs, ident)
         .map(|tokens| {
-            Punctuated::<LitStr, Token![,]>::parse_terminated
+            Punctuated::<NestedMeta, Token![,]>::parse_terminated
                 .parse2(tokens)
                 .unwrap()
         })
-        .unwrap_or_default();
-    let mut iter = attr.iter();
-    let name = iter.next().map(|lit| lit.clone());
-    let description = iter.next().map(|lit| lit.clone());
+        .unwrap_or_default()
+}
+
+fn unwrap_name_description(
+    attrs: &Vec<Attribute>,
+    ident: &str,
+) -> (Option<LitStr>, Option<LitStr>) {
+    let mut iter = parse_attr(attrs, ident)
+        .into_iter()
+        .filter_map(|nested| match nested {
+            NestedMeta::Lit(Lit::Str(lit)) => Some(lit),
+            _ => None,
+        });
+    let name = iter.next();
+    let description = iter.next();
     (name, description)
 }
 
+fn lit_f32(nested: &NestedMeta) -> Option<f32> {
+    match nested {
+        NestedMeta::Lit(Lit::Float(lit)) => lit.base10_parse().ok(),
+        NestedMeta::Lit(Lit::Int(lit)) => lit.base10_parse().ok(),
+        _ => None,
+    }
+}
+
+fn option_tokens<T: quote::ToTokens>(value: &Option<T>) -> TokenStream2 {
+    match value {
+        Some(value) => quote! { Some(#value) },
+        None => quote! { None },
+    }
+}
+
+// rejects value params outside of [min, max], cables and tracks can still go anywhere
+fn range_check(name: &Option<LitStr>, min: Literal, max: Literal) -> TokenStream2 {
+    quote! {
+        if let crate::types::InternalParam::Value { value } = new_param {
+            if *value < #min || *value > #max {
+                return Err(anyhow!(
+                    "{} is out of range for {}, which takes {} to {}",
+                    value,
+                    #name,
+                    #min,
+                    #max
+                ));
+            }
+        }
+    }
+}
+
+// the last segment of a field's type, e.g. InternalParam for crate::types::InternalParam
+fn type_name(ty: &Type) -> Option<String> {
+    match ty {
+        Type::Path(path) => path
+            .path
+            .segments
+            .last()
+            .map(|segment| segment.ident.to_string()),
+        _ => None,
+    }
+}
+
+// parses durations like "10ms", "500us" or "1s" into seconds
+fn parse_seconds(lit: &LitStr) -> syn::Result<f32> {
+    let value = lit.value();
+    let error =
+        || syn::Error::new_spanned(lit, format!("{} is not a duration like \"10ms\"", value));
+    let (number, scale) = if let Some(number) = value.strip_suffix("ms") {
+        (number, 1e-3)
+    } else if let Some(number) = value.strip_suffix("us") {
+        (number, 1e-6)
+    } else if let Some(number) = value.strip_suffix('s') {
+        (number, 1.0)
+    } else {
+        return Err(error());
+    };
+    match number.trim().parse::<f32>() {
+        Ok(number) if number >= 0.0 => Ok(number * scale),
+        _ => Err(error()),
+    }
+}
+
+// Fields other than InternalParams, SmoothedParams and GateParams are enums deriving ParamEnum.
+// They're set with a text param naming one of the enum's options and go back to their default
+// when disconnected.
+fn enum_update(f: &Field, f_name: &Option<Ident>, name: &Option<LitStr>) -> TokenStream2 {
+    quote_spanned! {f.span()=>
+        #name => match new_param {
+            crate::types::InternalParam::Text { value, .. } => {
+                match crate::types::ParamEnum::from_name(value.as_str()) {
+                    Some(option) => {
+                        self.#f_name = option;
+                        Ok(())
+                    }
+                    None => Err(anyhow!("{} is not an option for {}", value, #name)),
+                }
+            }
+            crate::types::InternalParam::Disconnected => {
+                self.#f_name = Default::default();
+                Ok(())
+            }
+            _ => Err(anyhow!("{} can only be set to one of its options", #name)),
+        }
+    }
+}
+
+// The options that can follow a param's name and description, e.g.
+// #[param("cutoff", "cutoff frequency", unit = "Hz", range(20, 20000), group = "Filter")]
+// The description can also be given as description = "...", and the name left out to use the
+// field's name in kebab case.
+// smooth = "10ms" glides the value of a SmoothedParam field to each change over about that long.
+// trigger or gate mark a GateParam field as one, for UIs to show a button.
+#[derive(Default)]
+struct ParamOptions {
+    description: Option<LitStr>,
+    display: Option<LitStr>,
+    unit: Option<LitStr>,
+    range: Option<(f32, f32)>,
+    step: Option<f32>,
+    group: Option<LitStr>,
+    // seconds
+    smooth: Option<f32>,
+    // Trigger or Gate
+    kind: Option<Ident>,
+}
+
+impl ParamOptions {
+    fn parse(attrs: &Vec<Attribute>) -> syn::Result<Self> {
+        let mut options = ParamOptions::default();
+        for nested in parse_attr(attrs, "param") {
+            match nested {
+                NestedMeta::Lit(Lit::Str(_)) => {}
+                NestedMeta::Meta(Meta::NameValue(ref option)) => {
+                    let key = option.path.get_ident().map(|ident| ident.to_string());
+                    match (key.as_deref(), &option.lit) {
+                        (Some("description"), Lit::Str(lit)) => {
+                            options.description = Some(lit.clone())
+                        }
+                        (Some("display"), Lit::Str(lit)) => options.display = Some(lit.clone()),
+                        (Some("unit"), Lit::Str(lit)) => options.unit = Some(lit.clone()),
+                        (Some("group"), Lit::Str(lit)) => options.group = Some(lit.clone()),
+                        (Some("smooth"), Lit::Str(lit)) => {
+                            options.smooth = Some(parse_seconds(lit)?)
+                        }
+                        (Some("step"), lit) => match lit_f32(&NestedMeta::Lit(lit.clone())) {
+                            Some(step) => options.step = Some(step),
+                            None => {
+                                return Err(syn::Error::new_spanned(
+                                    lit,
+                                    "param step must be a number",
+                                ))
+                            }
+                        },
+                        _ => {
+                            return Err(syn::Error::new_spanned(option, "unsupported param option"))
+                        }
+                    }
+                }
+                NestedMeta::Meta(Meta::List(ref list)) if list.path.is_ident("range") => {
+                    let bounds: Vec<_> = list.nested.iter().map(lit_f32).collect();
+                    match bounds.as_slice() {
+                        [Some(min), Some(max)] if min <= max => options.range = Some((*min, *max)),
+                        _ => {
+                            return Err(syn::Error::new_spanned(
+                                list,
+                                "param range must be range(min, max)",
+                            ))
+                        }
+                    }
+                }
+                NestedMeta::Meta(Meta::Path(ref path))
+                    if path.is_ident("trigger") || path.is_ident("gate") =>
+                {
+                    let kind = path.get_ident().unwrap().to_string().to_case(Case::Pascal);
+                    options.kind = Some(Ident::new(&kind, path.span()));
+                }
+                other => return Err(syn::Error::new_spanned(other, "unsupported param option")),
+            }
+        }
+        Ok(options)
+    }
+}
+
 fn map_name_description<F, B>(fields: &FieldsNamed, ident: &str, mut closure: F) -> Vec<B>
 where
     F: FnMut(&Field, Option<Ident>, Option<LitStr>, Option<LitStr>) -> B,
@@ -111,33 +284,134 @@ where
 
 fn impl_params_macro(ast: &DeriveInput) -> TokenStream {
     let name = &ast.ident;
-    let (inserts, updates, schemas) = match ast.data {
+    let (inserts, updates, inits, schemas) = match ast.data {
         Data::Struct(ref data) => match data.fields {
             Fields::Named(ref fields) => {
                 let v = map_name_description(fields, "param", |f, f_name, name, description| {
-                    (
-                        quote_spanned! {f.span()=>
-                            state.insert(#name.to_owned(), self.#f_name.to_param());
-                        },
-                        quote_spanned! {f.span()=>
-                            #name => {
-                                if self.#f_name != *new_param {
-                                    self.#f_name = new_param.clone();
-                                }
-                                Ok(())
+                    let options = ParamOptions::parse(&f.attrs)?;
+                    let name = name.or_else(|| {
+                        f_name.as_ref().map(|ident| {
+                            LitStr::new(&ident.to_string().to_case(Case::Kebab), ident.span())
+                        })
+                    });
+                    let description = match options.description.clone().or(description) {
+                        Some(description) => description,
+                        None => {
+                            return Err(syn::Error::new_spanned(f, "param needs a description"))
+                        }
+                    };
+                    let display = option_tokens(&options.display);
+                    let unit = option_tokens(&options.unit);
+                    let group = option_tokens(&options.group);
+                    let step = option_tokens(&options.step.map(Literal::f32_suffixed));
+                    let (range, range_check) = match options.range {
+                        Some((min, max)) => {
+                            let (min, max) =
+                                (Literal::f32_suffixed(min), Literal::f32_suffixed(max));
+                            (quote! { Some((#min, #max)) }, range_check(&name, min, max))
+                        }
+                        None => (quote! { None }, quote! {}),
+                    };
+                    let type_name = type_name(&f.ty);
+                    if options.smooth.is_some() && type_name.as_deref() != Some("SmoothedParam") {
+                        return Err(syn::Error::new_spanned(
+                            &f.ty,
+                            "smooth is only supported on SmoothedParam fields",
+                        ));
+                    }
+                    if options.kind.is_some() && type_name.as_deref() != Some("GateParam") {
+                        return Err(syn::Error::new_spanned(
+                            &f.ty,
+                            "trigger and gate are only supported on GateParam fields",
+                        ));
+                    }
+                    let kind = option_tokens(
+                        &options
+                            .kind
+                            .as_ref()
+                            .map(|kind| quote! { crate::types::PortKind::#kind }),
+                    );
+                    let init = match options.smooth {
+                        Some(time) => {
+                            let time = Literal::f32_suffixed(time);
+                            quote_spanned! {f.span()=>
+                                self.#f_name.time = #time;
                             }
-                        },
+                        }
+                        None => quote! {},
+                    };
+                    let (insert, update, choices) = match type_name.as_deref() {
+                        Some("InternalParam") => (
+                            quote_spanned! {f.span()=>
+                                state.insert(#name.to_owned(), self.#f_name.to_param());
+                            },
+                            quote_spanned! {f.span()=>
+                                #name => {
+                                    #range_check
+                                    if self.#f_name != *new_param {
+                                        self.#f_name = new_param.clone();
+                                    }
+                                    Ok(())
+                                }
+                            },
+                            quote! { None },
+                        ),
+                        Some("SmoothedParam") | Some("GateParam") => (
+                            quote_spanned! {f.span()=>
+                                state.insert(#name.to_owned(), self.#f_name.param.to_param());
+                            },
+                            quote_spanned! {f.span()=>
+                                #name => {
+                                    #range_check
+                                    if self.#f_name.param != *new_param {
+                                        self.#f_name.param = new_param.clone();
+                                    }
+                                    Ok(())
+                                }
+                            },
+                            quote! { None },
+                        ),
+                        _ => {
+                            let ty = &f.ty;
+                            (
+                                quote_spanned! {f.span()=>
+                                    state.insert(#name.to_owned(), crate::types::Param::Text {
+                                        value: crate::types::ParamEnum::name(&self.#f_name)
+                                            .to_owned(),
+                                    });
+                                },
+                                enum_update(f, &f_name, &name),
+                                quote! { Some(<#ty as crate::types::ParamEnum>::OPTIONS) },
+                            )
+                        }
+                    };
+                    Ok((
+                        insert,
+                        update,
+                        init,
                         quote_spanned! {f.span()=>
                             crate::types::PortSchema {
                                 name: #name,
                                 description: #description,
+                                display: #display,
+                                unit: #unit,
+                                range: #range,
+                                step: #step,
+                                options: #choices,
+                                group: #group,
+                                kind: #kind,
                             },
                         },
-                    )
+                    ))
                 });
-                let insert_iter = v.iter().map(|(insert, _, _)| insert);
-                let update_iter = v.iter().map(|(_, update, _)| update);
-                let schema_iter = v.iter().map(|(_, _, schema)| schema);
+                let v = match v.into_iter().collect::<syn::Result<Vec<_>>>() {
+                    Ok(v) => v,
+                    Err(err) => return err.to_compile_error().into(),
+                };
+                let insert_iter = v.iter().map(|(insert, _, _, _)| insert);
+                let update_iter = v.iter().map(|(_, update, _, _)| update);
+                let init_iter = v.iter().map(|(_, _, init, _)| init);
+                let schema_iter = v.iter().map(|(_, _, _, schema)| schema);
                 (
                     quote! {
                         #(#insert_iter)*
@@ -145,6 +419,9 @@ fn impl_params_macro(ast: &DeriveInput) -> TokenStream {
                     quote! {
                         #(#update_iter)*
                     },
+                    quote! {
+                        #(#init_iter)*
+                    },
                     quote! {
                         #(#schema_iter)*
                     },
@@ -174,6 +451,9 @@ fn impl_params_macro(ast: &DeriveInput) -> TokenStream {
                     )),
                 }
             }
+            fn init(&mut self) {
+                #inits
+            }
             fn get_schema() -> &'static [crate::types::PortSchema] {
                 &[
                     #schemas
@@ -184,7 +464,7 @@ fn impl_params_macro(ast: &DeriveInput) -> TokenStream {
     gen.into()
 }
 
-#[proc_macro_derive(Module, attributes(output, module))]
+#[proc_macro_derive(Module, attributes(output, module, state))]
 pub fn module_macro_derive(input: TokenStream) -> TokenStream {
     // Construct a representation of Rust code as a syntax tree
     // that we can manipulate
@@ -228,6 +508,7 @@ fn impl_module_macro(ast: &DeriveInput) -> TokenStream {
                             crate::types::PortSchema {
                                 name: #output_name,
                                 description: #description,
+                                ..crate::types::PortSchema::DEFAULT
                             },
                         },
                     )
@@ -237,6 +518,33 @@ fn impl_module_macro(ast: &DeriveInput) -> TokenStream {
         },
         Data::Enum(_) | Data::Union(_) => unimplemented!(),
     };
+    let state_fields: Vec<_> = match ast.data {
+        Data::Struct(ref data) => match data.fields {
+            Fields::Named(ref fields) => fields
+                .named
+                .iter()
+                .filter(|f| f.attrs.iter().any(|attr| attr.path.is_ident("state")))
+                .map(|f| {
+                    let name = f.ident.clone().unwrap();
+                    let key = LitStr::new(&name.to_string(), name.span());
+                    (
+                        quote_spanned! {f.span()=>
+                            state.insert(#key.to_owned(), serde_json::to_value(&module.#name)?);
+                        },
+                        quote_spanned! {f.span()=>
+                            if let Some(value) = state.get(#key) {
+                                module.#name = serde_json::from_value(value.clone())?;
+                            }
+                        },
+                    )
+                })
+                .collect(),
+            Fields::Unnamed(_) | Fields::Unit => unimplemented!(),
+        },
+        Data::Enum(_) | Data::Union(_) => unimplemented!(),
+    };
+    let state_getters = state_fields.iter().map(|(getter, _)| getter);
+    let state_setters = state_fields.iter().map(|(_, setter)| setter);
     let output_names = outputs.iter().map(|(idents, _, _, _)| idents);
     let output_assignments = outputs.iter().map(|(_, assignment, _, _)| assignment);
     let output_retrievals = outputs.iter().map(|(_, _, retrieval, _)| retrieval);
@@ -261,6 +569,7 @@ fn impl_module_macro(ast: &DeriveInput) -> TokenStream {
             outputs: parking_lot::RwLock<#output_struct_name>,
             module: parking_lot::Mutex<#name>,
             processed: core::sync::atomic::AtomicBool,
+            metrics: crate::metrics::MetricsAccumulator,
             sample_rate: f32
         }
 
@@ -276,13 +585,24 @@ fn impl_module_macro(ast: &DeriveInput) -> TokenStream {
                     core::sync::atomic::Ordering::Acquire,
                     core::sync::atomic::Ordering::Relaxed,
                 ) {
-                    let mut module = self.module.lock();
-                    module.update(self.sample_rate);
-                    let mut outputs = self.outputs.try_write_for(core::time::Duration::from_millis(10)).unwrap();
-                    #(#output_assignments)*
+                    let elapsed = crate::metrics::measure(|| {
+                        let mut module = self.module.lock();
+                        module.update(self.sample_rate);
+                        let mut outputs = self.outputs.try_write_for(core::time::Duration::from_millis(10)).unwrap();
+                        #(#output_assignments)*
+                    });
+                    self.metrics.record(elapsed);
                 }
             }
 
+            fn commit_metrics(&self) -> () {
+                self.metrics.commit();
+            }
+
+            fn get_metrics(&self) -> crate::metrics::ModuleMetrics {
+                self.metrics.snapshot()
+            }
+
             fn get_sample(&self, port: &String) -> Result<f32> {
                 self.update();
                 match port.as_str() {
@@ -302,9 +622,26 @@ fn impl_module_macro(ast: &DeriveInput) -> TokenStream {
                     module_type: #module_name.to_owned(),
                     id: self.id,
                     params: self.module.lock().params.get_params_state(),
+                    metrics: self.metrics.snapshot(),
                 }
             }
 
+            fn get_internal_state(&self) -> Result<serde_json::Value> {
+                #[allow(unused_variables)]
+                let module = self.module.lock();
+                #[allow(unused_mut)]
+                let mut state = serde_json::Map::new();
+                #(#state_getters)*
+                Ok(serde_json::Value::Object(state))
+            }
+
+            fn set_internal_state(&self, state: &serde_json::Value) -> Result<()> {
+                #[allow(unused_variables, unused_mut)]
+                let mut module = self.module.lock();
+                #(#state_setters)*
+                Ok(())
+            }
+
             fn update_param(&self, param_name: &String, new_param: &crate::types::InternalParam) -> Result<()> {
                 use crate::types::Params;
                 self.module.lock().params.update_param(param_name, new_param, #module_name)
@@ -316,11 +653,14 @@ fn impl_module_macro(ast: &DeriveInput) -> TokenStream {
         }
 
         fn #constructor_name(id: &uuid::Uuid, sample_rate: f32) -> Result<std::sync::Arc<Box<dyn crate::types::Sampleable>>> {
-            Ok(std::sync::Arc::new(Box::new(#struct_name {
+            use crate::types::Params;
+            let sampleable = #struct_name {
                 id: *id,
                 sample_rate,
                 ..#struct_name::default()
-            })))
+            };
+            sampleable.module.lock().params.init();
+            Ok(std::sync::Arc::new(Box::new(sampleable)))
         }
 
         impl crate::types::Module for #name {
@@ -342,3 +682,80 @@ fn impl_module_macro(ast: &DeriveInput) -> TokenStream {
     };
     gen.into()
 }
+
+#[proc_macro_derive(ParamEnum)]
+pub fn param_enum_macro_derive(input: TokenStream) -> TokenStream {
+    let ast: DeriveInput = syn::parse(input).unwrap();
+    impl_param_enum_macro(&ast)
+}
+
+// the text of a variant's doc comment, joined into one line
+fn doc_comment(attrs: &[Attribute]) -> String {
+    attrs
+        .iter()
+        .filter(|attr| attr.path.is_ident("doc"))
+        .filter_map(|attr| match attr.parse_meta() {
+            Ok(Meta::NameValue(doc)) => match doc.lit {
+                Lit::Str(lit) => Some(lit.value().trim().to_owned()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn impl_param_enum_macro(ast: &DeriveInput) -> TokenStream {
+    let name = &ast.ident;
+    let variants: Vec<_> = match ast.data {
+        Data::Enum(ref data) => data
+            .variants
+            .iter()
+            .map(|variant| {
+                let ident = variant.ident.clone();
+                let option_name = ident.to_string().to_case(Case::Kebab);
+                let option_name = LitStr::new(&option_name, ident.span());
+                let description = LitStr::new(&doc_comment(&variant.attrs), ident.span());
+                (ident, option_name, description)
+            })
+            .collect(),
+        Data::Struct(_) | Data::Union(_) => unimplemented!(),
+    };
+    let options = variants.iter().map(|(_, option_name, description)| {
+        quote! {
+            crate::types::ParamOption {
+                name: #option_name,
+                description: #description,
+            },
+        }
+    });
+    let from_names = variants.iter().map(|(ident, option_name, _)| {
+        quote! {
+            #option_name => Some(#name::#ident),
+        }
+    });
+    let names = variants.iter().map(|(ident, option_name, _)| {
+        quote! {
+            #name::#ident => #option_name,
+        }
+    });
+    let gen = quote! {
+        impl crate::types::ParamEnum for #name {
+            const OPTIONS: &'static [crate::types::ParamOption] = &[
+                #(#options)*
+            ];
+            fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    #(#from_names)*
+                    _ => None,
+                }
+            }
+            fn name(&self) -> &'static str {
+                match self {
+                    #(#names)*
+                }
+            }
+        }
+    };
+    gen.into()
+}
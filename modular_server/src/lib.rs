@@ -1,36 +1,103 @@
 pub use modular_core::crossbeam_channel;
-use modular_core::crossbeam_channel::unbounded;
-use std::thread::JoinHandle;
+use modular_core::crossbeam_channel::{unbounded, Sender};
+use std::{
+    path::{Path, PathBuf},
+    thread::JoinHandle,
+    time::Duration,
+};
 
-use modular_core::Modular;
+use modular_core::{
+    message::InputMessage, render::render_to_file, types::AudioConfig, Modular,
+};
 pub use rosc;
 use server::spawn_server;
+use watch::{read_patch_file, watch_patch_file};
 
 mod osc;
 mod server;
+mod watch;
+
+// the most one udp packet can carry, which is what both ends read into
+pub const MAX_PACKET_SIZE: usize = 65507;
+
+pub struct ServerConfig {
+    pub client_address: String,
+    // The interface to listen on, 0.0.0.0 to accept messages from other machines. There's no
+    // authentication, so anything that can reach the port can change the patch.
+    pub bind_address: String,
+    pub port: String,
+    // loaded at startup and reloaded whenever it changes
+    pub patch_file: Option<PathBuf>,
+    // the platform's default audio host when None
+    pub host: Option<String>,
+    pub audio_config: AudioConfig,
+    // every patch change is appended here as json lines, and only kept in memory when None
+    pub event_log: Option<PathBuf>,
+    // the only place clients can have recordings and renders written to
+    pub recordings: PathBuf,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            client_address: "127.0.0.1:7813".to_owned(),
+            bind_address: "127.0.0.1".to_owned(),
+            port: "7812".to_owned(),
+            patch_file: None,
+            host: None,
+            audio_config: AudioConfig::default(),
+            event_log: None,
+            recordings: PathBuf::from("recordings"),
+        }
+    }
+}
 
 pub fn spawn(
-    client_address: String,
-    port: String,
+    config: ServerConfig,
 ) -> (
     JoinHandle<anyhow::Result<()>>,
     JoinHandle<()>,
     JoinHandle<()>,
+    Sender<InputMessage>,
 ) {
     let (incoming_tx, incoming_rx) = unbounded();
     let (outgoing_tx, outgoing_rx) = unbounded();
 
-    let _modular_handle = Modular::spawn(incoming_rx, outgoing_tx);
+    let _modular_handle = Modular::spawn(
+        incoming_rx,
+        outgoing_tx,
+        config.host,
+        config.audio_config,
+        config.event_log,
+        config.recordings,
+    );
+
+    if let Some(patch_file) = config.patch_file {
+        watch_patch_file(patch_file, incoming_tx.clone());
+    }
 
     let (_receiving_server_handle, _sending_server_handle) = spawn_server(
-        client_address.to_owned(),
-        port.to_owned(),
-        incoming_tx,
+        config.client_address,
+        config.bind_address,
+        config.port,
+        incoming_tx.clone(),
         outgoing_rx,
     );
     (
         _modular_handle,
         _receiving_server_handle,
         _sending_server_handle,
+        incoming_tx,
     )
 }
+
+// Renders a patch file straight to a wav, without an audio device or the OSC server.
+pub fn render(
+    patch_file: &Path,
+    out: &str,
+    duration: Duration,
+    sample_rate: f32,
+) -> anyhow::Result<()> {
+    let graph = read_patch_file(patch_file)?;
+    render_to_file(&graph, duration, sample_rate, out)
+}
@@ -1,34 +1,101 @@
 extern crate anyhow;
+#[macro_use]
 extern crate clap;
 extern crate ctrlc;
 extern crate modular_core;
 extern crate rosc;
 
 use clap::{App, Arg, ArgMatches};
-use modular_server::spawn;
+use modular_core::{message::InputMessage, render::MAX_RENDER_LENGTH, types::AudioConfig};
+use modular_server::{render, spawn, ServerConfig};
+use std::path::{Path, PathBuf};
+use std::process;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+
+const DEFAULT_RENDER_SAMPLE_RATE: u32 = 48000;
 
 fn main() {
     let matches = get_matches();
 
+    if let Some(render_file) = matches.value_of(RENDER_ARG) {
+        let out = matches.value_of(OUT_ARG).unwrap();
+        let duration = value_t_or_exit!(matches, DURATION_ARG, f32);
+        if !duration.is_finite() || duration > MAX_RENDER_LENGTH.as_secs_f32() {
+            eprintln!(
+                "error: the duration must be at most {} seconds",
+                MAX_RENDER_LENGTH.as_secs()
+            );
+            process::exit(1);
+        }
+        let sample_rate =
+            value_t!(matches, SAMPLE_RATE_ARG, u32).unwrap_or(DEFAULT_RENDER_SAMPLE_RATE);
+        if let Err(err) = render(
+            Path::new(render_file),
+            out,
+            Duration::from_secs_f32(duration.max(0.0)),
+            sample_rate as f32,
+        ) {
+            eprintln!("error: {}", err);
+            process::exit(1);
+        }
+        return;
+    }
+
     let running = Arc::new(AtomicBool::new(true));
     let client_address = matches.value_of(CLIENT_ARG).unwrap();
+    let bind_address = matches.value_of(BIND_ARG).unwrap();
     let port = matches.value_of(PORT_ARG).unwrap();
+    let patch_file = matches.value_of(PATCH_ARG).map(PathBuf::from);
+    let audio_config = AudioConfig {
+        sample_rate: value_t!(matches, SAMPLE_RATE_ARG, u32).ok(),
+        buffer_size: value_t!(matches, BUFFER_SIZE_ARG, u32).ok(),
+        channels: value_t!(matches, CHANNELS_ARG, u16).ok(),
+    };
 
-    let (_modular_handle, _receiving_server_handle, _sending_server_handle) =
-        spawn(client_address.to_owned(), port.to_owned());
+    let (modular_handle, _receiving_server_handle, _sending_server_handle, incoming_tx) =
+        spawn(ServerConfig {
+            client_address: client_address.to_owned(),
+            bind_address: bind_address.to_owned(),
+            port: port.to_owned(),
+            patch_file,
+            host: matches.value_of(HOST_ARG).map(String::from),
+            audio_config,
+            event_log: matches.value_of(EVENT_LOG_ARG).map(PathBuf::from),
+            recordings: PathBuf::from(matches.value_of(RECORDINGS_ARG).unwrap()),
+        });
     let r = running.clone();
     ctrlc::set_handler(move || {
         r.store(false, Ordering::SeqCst);
     })
     .expect("Error setting Ctrl-C handler");
 
-    while running.load(Ordering::SeqCst) {}
+    // the patch also stops by itself when a client sends /shutdown
+    while running.load(Ordering::SeqCst) && !modular_handle.is_finished() {}
+
+    // let the patch fade out and finish writing before the process exits
+    let _ = incoming_tx.send(InputMessage::Shutdown);
+    match modular_handle.join() {
+        Ok(Err(err)) => eprintln!("error: {}", err),
+        Err(_) => eprintln!("error: the audio thread panicked"),
+        Ok(Ok(())) => {}
+    }
 }
 
 const CLIENT_ARG: &str = "client";
+const BIND_ARG: &str = "bind";
 const PORT_ARG: &str = "port";
+const PATCH_ARG: &str = "patch";
+const HOST_ARG: &str = "host";
+const SAMPLE_RATE_ARG: &str = "sample-rate";
+const BUFFER_SIZE_ARG: &str = "buffer-size";
+const CHANNELS_ARG: &str = "channels";
+const RENDER_ARG: &str = "render";
+const OUT_ARG: &str = "out";
+const DURATION_ARG: &str = "duration";
+const EVENT_LOG_ARG: &str = "event-log";
+const RECORDINGS_ARG: &str = "recordings";
 
 fn get_matches<'a>() -> ArgMatches<'a> {
     App::new("Modular")
@@ -42,6 +109,17 @@ fn get_matches<'a>() -> ArgMatches<'a> {
                 .default_value("127.0.0.1:7813")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name(BIND_ARG)
+                .long(BIND_ARG)
+                .value_name("IP_ADDRESS")
+                .help(
+                    "address to listen on, 0.0.0.0 to accept OSC from other machines, \
+                     which can then change the patch without authenticating",
+                )
+                .default_value("127.0.0.1")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name(PORT_ARG)
                 .long(PORT_ARG)
@@ -49,5 +127,78 @@ fn get_matches<'a>() -> ArgMatches<'a> {
                 .default_value("7812")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name(PATCH_ARG)
+                .long(PATCH_ARG)
+                .value_name("FILE")
+                .help("patch json to load, reloaded whenever it changes")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(HOST_ARG)
+                .long(HOST_ARG)
+                .value_name("HOST")
+                .help("audio host to use, e.g. ALSA, or JACK when built with the jack feature")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(SAMPLE_RATE_ARG)
+                .long(SAMPLE_RATE_ARG)
+                .value_name("HZ")
+                .help("sample rate to request from the audio device")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(BUFFER_SIZE_ARG)
+                .long(BUFFER_SIZE_ARG)
+                .value_name("FRAMES")
+                .help("buffer size to request from the audio device")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(CHANNELS_ARG)
+                .long(CHANNELS_ARG)
+                .value_name("CHANNELS")
+                .help("number of output channels to request from the audio device")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(EVENT_LOG_ARG)
+                .long(EVENT_LOG_ARG)
+                .value_name("FILE")
+                .help("json lines file to append every patch change to")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(RECORDINGS_ARG)
+                .long(RECORDINGS_ARG)
+                .value_name("DIR")
+                .help("directory that recordings and renders requested by clients are written to")
+                .default_value("recordings")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(RENDER_ARG)
+                .long(RENDER_ARG)
+                .value_name("FILE")
+                .help("render this patch json to a wav and exit, without opening an audio device")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(OUT_ARG)
+                .long(OUT_ARG)
+                .value_name("FILE")
+                .help("wav file to render to")
+                .default_value("out.wav")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name(DURATION_ARG)
+                .long(DURATION_ARG)
+                .value_name("SECONDS")
+                .help("length of the render")
+                .default_value("30")
+                .takes_value(true),
+        )
         .get_matches()
 }
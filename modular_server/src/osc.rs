@@ -1,12 +1,24 @@
-use modular_core::crossbeam_channel::Sender;
+use modular_core::crossbeam_channel::{unbounded, Sender};
 use modular_core::{
+    library::PatchMetadata,
     message::{InputMessage, OutputMessage},
-    types::{ModuleState, Param, Playmode},
+    render::MAX_RENDER_LENGTH,
+    snapshot::MAX_MORPH_LENGTH,
+    types::{ModuleState, OutputRoute, Param, Playmode, PortSchema},
     uuid::Uuid,
 };
-use rosc::OscType::{Float as OscFloat, Int as OscInt, Nil as OscNil, String as OscStr};
+use rosc::OscType::{
+    Float as OscFloat, Int as OscInt, Long as OscLong, Nil as OscNil, String as OscStr,
+};
 use rosc::{OscBundle, OscMessage, OscPacket, OscType};
-use std::vec;
+use serde::Serialize;
+use std::{
+    time::{Duration, UNIX_EPOCH},
+    vec,
+};
+
+// well under what fits in one packet, leaving room for the address and the other args
+const MAX_PAGE_SIZE: usize = 32 * 1024;
 
 fn bndl(content: Vec<OscPacket>) -> OscPacket {
     OscPacket::Bundle(OscBundle {
@@ -26,6 +38,7 @@ fn param_to_osc_type_vec(param: &Param) -> Vec<OscType> {
     match param {
         Param::Value { value } => [OscStr("value".into()), OscFloat(*value)].into(),
         Param::Note { value } => [OscStr("note".into()), OscInt(*value as i32)].into(),
+        Param::Text { value } => [OscStr("text".into()), OscStr(value.clone())].into(),
         Param::Cable { module, port } => [
             OscStr("cable".into()),
             OscStr(module.to_string()),
@@ -37,12 +50,66 @@ fn param_to_osc_type_vec(param: &Param) -> Vec<OscType> {
     }
 }
 
+// the description first, for clients that only read that, then the whole schema as json
+fn port_schema_args(port: &PortSchema) -> Vec<OscType> {
+    let mut args = vec![OscStr(port.description.to_owned())];
+    if let Ok(json) = serde_json::to_string(port) {
+        args.push(OscStr(json));
+    }
+    args
+}
+
+// Sends a list as json arrays split across as many messages as it takes to keep each one small
+// enough for a packet. Every message starts with its page index and the page count, so a client
+// can tell when it has the whole list.
+fn json_pages<T: Serialize>(addr: &str, items: &[T]) -> Vec<OscPacket> {
+    let mut pages: Vec<Vec<String>> = vec![vec![]];
+    let mut size = 0;
+    for item in items.iter() {
+        let json = match serde_json::to_string(item) {
+            Ok(json) => json,
+            Err(err) => return vec![msg("/error", vec![OscStr(err.to_string())])],
+        };
+        if size + json.len() > MAX_PAGE_SIZE && size > 0 {
+            pages.push(vec![]);
+            size = 0;
+        }
+        size += json.len() + 1;
+        pages.last_mut().unwrap().push(json);
+    }
+    let count = pages.len();
+    pages
+        .into_iter()
+        .enumerate()
+        .map(|(index, page)| {
+            msg(
+                addr,
+                vec![
+                    OscInt(index as i32),
+                    OscInt(count as i32),
+                    OscStr(format!("[{}]", page.join(","))),
+                ],
+            )
+        })
+        .collect()
+}
+
 fn make_module_state_bndl(state: &ModuleState) -> OscPacket {
     let base = format!("/module/{}", state.id);
     let module_type = state.module_type.clone();
     bndl(
         [
-            vec![msg(&base, vec![OscStr(module_type)])],
+            vec![
+                msg(&base, vec![OscStr(module_type)]),
+                msg(
+                    &format!("{}/metrics", &base),
+                    vec![
+                        OscLong(state.metrics.total_ns as i64),
+                        OscLong(state.metrics.max_ns as i64),
+                        OscLong(state.metrics.samples as i64),
+                    ],
+                ),
+            ],
             state
                 .params
                 .iter()
@@ -61,6 +128,34 @@ fn make_module_state_bndl(state: &ModuleState) -> OscPacket {
 pub fn message_to_osc(message: OutputMessage) -> Vec<OscPacket> {
     match message {
         OutputMessage::Echo(s) => vec![msg("/echo", vec![OscStr(s)])],
+        OutputMessage::AudioConfig(config) => vec![msg(
+            "/audio-config",
+            vec![
+                config
+                    .sample_rate
+                    .map_or(OscNil, |sample_rate| OscInt(sample_rate as i32)),
+                config
+                    .buffer_size
+                    .map_or(OscNil, |buffer_size| OscInt(buffer_size as i32)),
+                config
+                    .channels
+                    .map_or(OscNil, |channels| OscInt(channels as i32)),
+            ],
+        )],
+        OutputMessage::OutputRoutes(routes) => {
+            let mut content = vec![msg("/output-routes", vec![])];
+            for (channel, route) in routes.into_iter() {
+                content.push(msg(
+                    "/output-route",
+                    vec![
+                        OscInt(channel as i32),
+                        OscStr(route.module.to_string()),
+                        OscStr(route.port),
+                    ],
+                ));
+            }
+            vec![bndl(content)]
+        }
         OutputMessage::Schema(schemas) => schemas
             .iter()
             .map(|schema| {
@@ -72,7 +167,7 @@ pub fn message_to_osc(message: OutputMessage) -> Vec<OscPacket> {
                     .map(|param| {
                         msg(
                             &format!("{}/param/{}", route, param.name),
-                            vec![OscStr(param.description.to_owned())],
+                            port_schema_args(param),
                         )
                     })
                     .collect();
@@ -82,7 +177,7 @@ pub fn message_to_osc(message: OutputMessage) -> Vec<OscPacket> {
                     .map(|output| {
                         msg(
                             &format!("{}/output/{}", route, output.name),
-                            vec![OscStr(output.description.to_owned())],
+                            port_schema_args(output),
                         )
                     })
                     .collect();
@@ -147,6 +242,55 @@ pub fn message_to_osc(message: OutputMessage) -> Vec<OscPacket> {
         OutputMessage::CreateTrack(id) => {
             vec![msg("/create-track", vec![OscStr(id.to_string())])]
         }
+        OutputMessage::SessionState(session_state) => match serde_json::to_string(&session_state) {
+            Ok(json) => vec![msg("/session-state", vec![OscStr(json)])],
+            Err(err) => vec![msg("/error", vec![OscStr(err.to_string())])],
+        },
+        OutputMessage::CreateScope(id) => {
+            vec![msg("/create-scope", vec![OscStr(id.to_string())])]
+        }
+        OutputMessage::ScopeError(id, err) => {
+            eprintln!("error: scope {}: {}", id, err);
+            vec![msg("/error", vec![OscStr(err)])]
+        }
+        OutputMessage::RecordingStarted(path) => {
+            vec![msg("/recording-started", vec![OscStr(path)])]
+        }
+        OutputMessage::RecordingStopped(path) => {
+            vec![msg("/recording-stopped", vec![OscStr(path)])]
+        }
+        OutputMessage::PatchList(patches) => json_pages("/patches", &patches),
+        OutputMessage::Snapshots(names) => {
+            vec![msg("/snapshots", names.into_iter().map(OscStr).collect())]
+        }
+        OutputMessage::Recovery(saved) => vec![msg(
+            "/recovery",
+            match saved.and_then(|saved| saved.duration_since(UNIX_EPOCH).ok()) {
+                Some(saved) => vec![OscLong(saved.as_secs() as i64)],
+                None => vec![OscNil],
+            },
+        )],
+        OutputMessage::HistoryDepth(undo, redo) => vec![msg(
+            "/history-depth",
+            vec![OscInt(undo as i32), OscInt(redo as i32)],
+        )],
+        OutputMessage::Events(events) => json_pages("/events", &events),
+        OutputMessage::Revision(revision) => {
+            vec![msg("/revision", vec![OscLong(revision as i64)])]
+        }
+        OutputMessage::Conflict(revision) => {
+            vec![msg("/conflict", vec![OscLong(revision as i64)])]
+        }
+        OutputMessage::Validation(errors) => json_pages("/validation", &errors),
+        OutputMessage::RenderFinished(path) => {
+            vec![msg("/render-finished", vec![OscStr(path)])]
+        }
+        OutputMessage::ScopeData(id, index, samples) => vec![msg(
+            &format!("/scope/{}", id),
+            std::iter::once(OscInt(index as i32))
+                .chain(samples.into_iter().map(OscFloat))
+                .collect(),
+        )],
     }
 }
 
@@ -156,6 +300,14 @@ fn send(message: InputMessage, tx: &Sender<InputMessage>) {
     }
 }
 
+pub fn collect_messages<I: Iterator<Item = OscPacket>>(packets: I) -> Vec<InputMessage> {
+    let (inner_tx, inner_rx) = unbounded();
+    for p in packets {
+        osc_to_message(p, &inner_tx);
+    }
+    inner_rx.try_iter().collect()
+}
+
 pub fn osc_to_message(packet: OscPacket, tx: &Sender<InputMessage>) {
     match packet {
         OscPacket::Message(message) => match message.addr.as_str() {
@@ -165,7 +317,169 @@ pub fn osc_to_message(packet: OscPacket, tx: &Sender<InputMessage>) {
                 }
             }
             "/schema" => send(InputMessage::Schema, tx),
+            "/audio-config" => send(InputMessage::GetAudioConfig, tx),
+            "/output-routes" => send(InputMessage::GetOutputRoutes, tx),
+            "/output-route" => match (
+                message.args.get(0),
+                message.args.get(1),
+                message.args.get(2),
+            ) {
+                (Some(OscInt(channel)), Some(OscStr(module)), Some(OscStr(port))) => {
+                    match Uuid::parse_str(module) {
+                        Ok(module) => send(
+                            InputMessage::SetOutputRoute(
+                                *channel as u16,
+                                Some(OutputRoute {
+                                    module,
+                                    port: port.clone(),
+                                }),
+                            ),
+                            tx,
+                        ),
+                        Err(err) => println!("{}", err),
+                    }
+                }
+                (Some(OscInt(channel)), None, None) => {
+                    send(InputMessage::SetOutputRoute(*channel as u16, None), tx)
+                }
+                _ => {}
+            },
             "/modules" => send(InputMessage::GetModules, tx),
+            "/session-state" => match message.args.get(0) {
+                Some(OscStr(json)) => match serde_json::from_str(json) {
+                    Ok(session_state) => send(InputMessage::SetSessionState(session_state), tx),
+                    Err(err) => println!("{}", err),
+                },
+                _ => send(InputMessage::GetSessionState, tx),
+            },
+            "/start-recording" => {
+                if let Some(OscStr(name)) = message.args.get(0) {
+                    send(InputMessage::StartRecording(name.clone()), tx);
+                }
+            }
+            "/stop-recording" => send(InputMessage::StopRecording, tx),
+            "/render" => {
+                if let (Some(OscStr(name)), Some(OscFloat(seconds))) =
+                    (message.args.get(0), message.args.get(1))
+                {
+                    // checked before converting since Duration panics on lengths it can't hold
+                    if seconds.is_finite() && *seconds <= MAX_RENDER_LENGTH.as_secs_f32() {
+                        send(
+                            InputMessage::Render(
+                                name.clone(),
+                                Duration::from_secs_f32(seconds.max(0.0)),
+                            ),
+                            tx,
+                        );
+                    } else {
+                        println!("{} is not a valid render length", seconds);
+                    }
+                }
+            }
+            "/patch-graph" => {
+                if let Some(OscStr(json)) = message.args.get(0) {
+                    match serde_json::from_str(json) {
+                        Ok(graph) => send(InputMessage::SetPatchGraph(graph), tx),
+                        Err(err) => println!("{}", err),
+                    }
+                }
+            }
+            "/validate-patch-graph" => {
+                if let Some(OscStr(json)) = message.args.get(0) {
+                    match serde_json::from_str(json) {
+                        Ok(graph) => send(InputMessage::ValidatePatchGraph(graph), tx),
+                        Err(err) => println!("{}", err),
+                    }
+                }
+            }
+            "/patches" => send(InputMessage::ListPatches, tx),
+            "/save-patch" => {
+                // name, then optionally an author and any number of tags
+                if let Some(OscStr(name)) = message.args.get(0) {
+                    let author = match message.args.get(1) {
+                        Some(OscStr(author)) if !author.is_empty() => Some(author.clone()),
+                        _ => None,
+                    };
+                    let tags = message
+                        .args
+                        .iter()
+                        .skip(2)
+                        .filter_map(|arg| match arg {
+                            OscStr(tag) => Some(tag.clone()),
+                            _ => None,
+                        })
+                        .collect();
+                    send(
+                        InputMessage::SavePatch(name.clone(), PatchMetadata { author, tags }),
+                        tx,
+                    );
+                }
+            }
+            "/load-patch" => {
+                if let Some(OscStr(name)) = message.args.get(0) {
+                    send(InputMessage::LoadPatch(name.clone()), tx);
+                }
+            }
+            "/delete-patch" => {
+                if let Some(OscStr(name)) = message.args.get(0) {
+                    send(InputMessage::DeletePatch(name.clone()), tx);
+                }
+            }
+            "/rename-patch" => {
+                if let (Some(OscStr(from)), Some(OscStr(to))) =
+                    (message.args.get(0), message.args.get(1))
+                {
+                    send(InputMessage::RenamePatch(from.clone(), to.clone()), tx);
+                }
+            }
+            "/capture-snapshot" => {
+                if let Some(OscStr(name)) = message.args.get(0) {
+                    send(InputMessage::CaptureSnapshot(name.clone()), tx);
+                }
+            }
+            "/recall-snapshot" => match (message.args.get(0), message.args.get(1)) {
+                // clamped before converting since Duration panics on lengths it can't hold
+                (Some(OscStr(name)), Some(OscFloat(seconds))) if seconds.is_finite() => send(
+                    InputMessage::RecallSnapshot(
+                        name.clone(),
+                        Duration::from_secs_f32(
+                            seconds.max(0.0).min(MAX_MORPH_LENGTH.as_secs_f32()),
+                        ),
+                    ),
+                    tx,
+                ),
+                (Some(OscStr(name)), None) => send(
+                    InputMessage::RecallSnapshot(name.clone(), Duration::from_secs(0)),
+                    tx,
+                ),
+                _ => {}
+            },
+            "/delete-snapshot" => {
+                if let Some(OscStr(name)) = message.args.get(0) {
+                    send(InputMessage::DeleteSnapshot(name.clone()), tx);
+                }
+            }
+            "/recovery" => send(InputMessage::GetRecovery, tx),
+            "/recover" => send(InputMessage::Recover, tx),
+            "/discard-recovery" => send(InputMessage::DiscardRecovery, tx),
+            "/shutdown" => send(InputMessage::Shutdown, tx),
+            "/history-depth" => send(InputMessage::GetHistoryDepth, tx),
+            "/events" => match message.args.get(0) {
+                Some(OscLong(revision)) => send(InputMessage::GetEvents(*revision as u64), tx),
+                _ => send(InputMessage::GetEvents(0), tx),
+            },
+            "/randomize-module" => {
+                if let (Some(OscStr(id)), Some(OscFloat(amount))) =
+                    (message.args.get(0), message.args.get(1))
+                {
+                    match Uuid::parse_str(id) {
+                        Ok(id) => send(InputMessage::RandomizeModule(id, *amount), tx),
+                        Err(err) => println!("{}", err),
+                    }
+                }
+            }
+            "/undo" => send(InputMessage::Undo, tx),
+            "/redo" => send(InputMessage::Redo, tx),
             "/delete-module" => {
                 if let Some(OscStr(id)) = message.args.get(0) {
                     send(
@@ -180,6 +494,71 @@ pub fn osc_to_message(packet: OscPacket, tx: &Sender<InputMessage>) {
                     );
                 }
             }
+            "/delete-scope" => {
+                if let Some(OscStr(id)) = message.args.get(0) {
+                    send(
+                        InputMessage::DeleteScope(match Uuid::parse_str(id) {
+                            Ok(id) => id,
+                            Err(err) => {
+                                println!("{}", err);
+                                return;
+                            }
+                        }),
+                        tx,
+                    );
+                }
+            }
+            "/arm-automation" => {
+                if let (Some(OscStr(id)), Some(OscStr(param_name)), Some(OscStr(track_id))) = (
+                    message.args.get(0),
+                    message.args.get(1),
+                    message.args.get(2),
+                ) {
+                    match (Uuid::parse_str(id), Uuid::parse_str(track_id)) {
+                        (Ok(id), Ok(track_id)) => send(
+                            InputMessage::ArmAutomation(id, param_name.clone(), track_id),
+                            tx,
+                        ),
+                        (Err(err), _) | (_, Err(err)) => println!("{}", err),
+                    }
+                }
+            }
+            "/disarm-automation" => {
+                if let (Some(OscStr(id)), Some(OscStr(param_name))) =
+                    (message.args.get(0), message.args.get(1))
+                {
+                    match Uuid::parse_str(id) {
+                        Ok(id) => send(InputMessage::DisarmAutomation(id, param_name.clone()), tx),
+                        Err(err) => println!("{}", err),
+                    }
+                }
+            }
+            "/create-scope" => {
+                if let (
+                    Some(OscStr(id)),
+                    Some(OscStr(module)),
+                    Some(OscStr(port)),
+                    Some(OscInt(decimation)),
+                ) = (
+                    message.args.get(0),
+                    message.args.get(1),
+                    message.args.get(2),
+                    message.args.get(3),
+                ) {
+                    match (Uuid::parse_str(id), Uuid::parse_str(module)) {
+                        (Ok(id), Ok(module)) => send(
+                            InputMessage::CreateScope(
+                                id,
+                                module,
+                                port.clone(),
+                                (*decimation).max(1) as u32,
+                            ),
+                            tx,
+                        ),
+                        (Err(err), _) | (_, Err(err)) => println!("{}", err),
+                    }
+                }
+            }
             addr => {
                 let s: Vec<&str> = addr.split("/").filter(|s| *s != "").collect();
                 let addr = (s.get(0), s.get(1), s.get(2), s.get(3), s.get(4));
@@ -201,6 +580,30 @@ pub fn osc_to_message(packet: OscPacket, tx: &Sender<InputMessage>) {
                         }),
                         tx,
                     );
+                } else if let (
+                    Some(&"module"),
+                    Some(id),
+                    Some(param),
+                    None,
+                    Some(OscFloat(value)),
+                    None,
+                ) = (addr.0, addr.1, addr.2, addr.3, args.0, args.1)
+                {
+                    // short form for controllers that can only send a bare float
+                    send(
+                        InputMessage::UpdateParam(
+                            match Uuid::parse_str(*id) {
+                                Ok(id) => id,
+                                Err(err) => {
+                                    println!("{}", err);
+                                    return;
+                                }
+                            },
+                            String::from(*param),
+                            Param::Value { value: *value },
+                        ),
+                        tx,
+                    );
                 } else if let (
                     Some(&"create-module"),
                     None,
@@ -261,6 +664,9 @@ pub fn osc_to_message(packet: OscPacket, tx: &Sender<InputMessage>) {
                                 ("note", Some(OscInt(note)), None, None) => Param::Note {
                                     value: note.clone() as u8,
                                 },
+                                ("text", Some(OscStr(text)), None, None) => Param::Text {
+                                    value: text.clone(),
+                                },
                                 ("disconnected", None, None, None) => Param::Disconnected,
                                 (param_type, _, _, _) => {
                                     println!("param type not value: {}", param_type);
@@ -274,8 +680,41 @@ pub fn osc_to_message(packet: OscPacket, tx: &Sender<InputMessage>) {
             }
         },
         OscPacket::Bundle(bundle) => {
-            for p in bundle.content {
-                osc_to_message(p, tx);
+            let mut content = bundle.content.into_iter();
+            match content.next() {
+                // a bundle starting with /at-revision only applies the message after it if nobody
+                // else has changed the patch since the sender last saw it
+                Some(OscPacket::Message(OscMessage { ref addr, ref args }))
+                    if addr == "/at-revision" =>
+                {
+                    let revision = match args.get(0) {
+                        Some(OscLong(revision)) => *revision as u64,
+                        _ => {
+                            println!("/at-revision needs a revision");
+                            return;
+                        }
+                    };
+                    let mut messages = collect_messages(content);
+                    if messages.len() != 1 {
+                        println!("/at-revision bundles must contain exactly one message");
+                        return;
+                    }
+                    send(
+                        InputMessage::AtRevision(revision, Box::new(messages.remove(0))),
+                        tx,
+                    );
+                }
+                // the rest of a bundle starting with /batch is applied all at once or not at all
+                Some(OscPacket::Message(OscMessage { ref addr, .. })) if addr == "/batch" => {
+                    send(InputMessage::Batch(collect_messages(content)), tx);
+                }
+                Some(p) => {
+                    osc_to_message(p, tx);
+                    for p in content {
+                        osc_to_message(p, tx);
+                    }
+                }
+                None => {}
             }
         }
     }
@@ -1,43 +1,249 @@
 use std::{
+    collections::HashMap,
+    iter,
     net::SocketAddrV4,
-    net::UdpSocket,
+    net::{SocketAddr, UdpSocket},
     str::FromStr,
+    sync::{Arc, Mutex},
     thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 use modular_core::crossbeam_channel::{Sender, Receiver};
 
 use modular_core::message::{InputMessage, OutputMessage};
-use rosc::encoder;
+use modular_core::uuid::Uuid;
+use rosc::{encoder, OscMessage, OscPacket, OscType};
 
-use crate::osc::{message_to_osc, osc_to_message};
+use crate::{
+    osc::{collect_messages, message_to_osc},
+    MAX_PACKET_SIZE,
+};
+
+// beyond this the subscriber heard from least recently is dropped to make room
+const MAX_SUBSCRIBERS: usize = 16;
+
+// A /subscribe from off the machine is answered with a /subscribe-challenge carrying a nonce, and
+// only takes effect once the nonce comes back in a /subscribe-confirm from the same address. That
+// way a spoofed source address, which never sees the challenge, can't be subscribed.
+const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_CHALLENGES: usize = 64;
+
+#[derive(Default)]
+struct Clients {
+    // extra destinations that asked for everything the client gets, e.g. TouchOSC or Max, with
+    // when each was last heard from
+    subscribers: HashMap<SocketAddr, Instant>,
+    // scopes created by a subscriber only stream to that subscriber
+    scope_owners: HashMap<Uuid, SocketAddr>,
+    // subscribe requests waiting to be confirmed, with their nonce and when they were made
+    challenges: HashMap<SocketAddr, (Uuid, Instant)>,
+}
+
+impl Clients {
+    // the nonce `addr` has to send back to subscribe, or None if too many are already waiting
+    fn challenge(&mut self, addr: SocketAddr) -> Option<Uuid> {
+        self.challenges
+            .retain(|_, (_, made)| made.elapsed() < CHALLENGE_TIMEOUT);
+        if !self.challenges.contains_key(&addr) && self.challenges.len() >= MAX_CHALLENGES {
+            return None;
+        }
+        let nonce = Uuid::new_v4();
+        self.challenges.insert(addr, (nonce, Instant::now()));
+        Some(nonce)
+    }
+
+    fn confirm(&mut self, addr: SocketAddr, nonce: Uuid) -> bool {
+        match self.challenges.get(&addr) {
+            Some((expected, made)) if *expected == nonce && made.elapsed() < CHALLENGE_TIMEOUT => {
+                self.challenges.remove(&addr);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn subscribe(&mut self, addr: SocketAddr, tx: &Sender<InputMessage>) {
+        if !self.subscribers.contains_key(&addr) && self.subscribers.len() >= MAX_SUBSCRIBERS {
+            let oldest = self
+                .subscribers
+                .iter()
+                .min_by_key(|(_, heard)| **heard)
+                .map(|(oldest, _)| *oldest);
+            if let Some(oldest) = oldest {
+                println!("Too many subscribers, unsubscribed {}", oldest);
+                self.unsubscribe(oldest, tx);
+            }
+        }
+        self.subscribers.insert(addr, Instant::now());
+    }
 
-pub fn start_sending_server(client_address: String, rx: Receiver<OutputMessage>) {
+    // also deletes the scopes the subscriber created, since nobody else is sent them
+    fn unsubscribe(&mut self, addr: SocketAddr, tx: &Sender<InputMessage>) {
+        self.subscribers.remove(&addr);
+        let owned: Vec<Uuid> = self
+            .scope_owners
+            .iter()
+            .filter(|(_, owner)| **owner == addr)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in owned {
+            self.scope_owners.remove(&id);
+            if let Err(e) = tx.send(InputMessage::DeleteScope(id)) {
+                println!("Error deleting scope {} of {}: {}", id, addr, e);
+            }
+        }
+    }
+}
+
+type SharedClients = Arc<Mutex<Clients>>;
+
+// A reply too large for one packet is replaced with an error saying so, since otherwise it would
+// be dropped without the client ever knowing it was sent.
+fn encode(packet: &OscPacket) -> Vec<u8> {
+    let msg_buf = encoder::encode(packet).unwrap();
+    if msg_buf.len() <= MAX_PACKET_SIZE {
+        return msg_buf;
+    }
+    let addr = match packet {
+        OscPacket::Message(message) => message.addr.as_str(),
+        OscPacket::Bundle(_) => "bundle",
+    };
+    let err = format!(
+        "the reply to {} is {} bytes, more than the {} that fit in one packet",
+        addr,
+        msg_buf.len(),
+        MAX_PACKET_SIZE
+    );
+    eprintln!("error: {}", err);
+    encoder::encode(&OscPacket::Message(OscMessage {
+        addr: "/error".to_owned(),
+        args: vec![OscType::String(err)],
+    }))
+    .unwrap()
+}
+
+// the uuid in the first argument, e.g. a scope's id or a subscribe nonce
+fn uuid_arg(message: &OscMessage) -> Option<Uuid> {
+    match message.args.get(0) {
+        Some(OscType::String(id)) => Uuid::parse_str(id).ok(),
+        _ => None,
+    }
+}
+
+pub fn start_sending_server(
+    client_address: String,
+    rx: Receiver<OutputMessage>,
+    clients: SharedClients,
+) {
     let host_addr = SocketAddrV4::from_str("0.0.0.0:0").unwrap();
     let to_addr = SocketAddrV4::from_str(&client_address).unwrap();
     println!("Sending to {}", to_addr);
     let sock = UdpSocket::bind(host_addr).unwrap();
 
     for message in rx {
+        let destinations: Vec<SocketAddr> = {
+            let mut clients = clients.lock().unwrap();
+            match message {
+                OutputMessage::ScopeData(ref id, _, _) => match clients.scope_owners.get(id) {
+                    Some(owner) => vec![*owner],
+                    None => vec![SocketAddr::V4(to_addr)],
+                },
+                // the scope doesn't exist, so it no longer has an owner
+                OutputMessage::ScopeError(ref id, _) => match clients.scope_owners.remove(id) {
+                    Some(owner) => vec![owner],
+                    None => vec![SocketAddr::V4(to_addr)],
+                },
+                _ => std::iter::once(SocketAddr::V4(to_addr))
+                    .chain(clients.subscribers.keys().cloned())
+                    .collect(),
+            }
+        };
         for packet in message_to_osc(message) {
-            let msg_buf = encoder::encode(&packet).unwrap();
-            sock.send_to(&msg_buf, to_addr).unwrap();
+            let msg_buf = encode(&packet);
+            for destination in destinations.iter() {
+                if let Err(err) = sock.send_to(&msg_buf, destination) {
+                    println!("Error sending to {}: {}", destination, err);
+                }
+            }
         }
     }
 }
 
-pub fn start_recieving_server(host_address: String, tx: Sender<InputMessage>) {
+pub fn start_recieving_server(
+    host_address: String,
+    tx: Sender<InputMessage>,
+    clients: SharedClients,
+) {
     let addr = SocketAddrV4::from_str(&host_address).unwrap();
     let sock = UdpSocket::bind(addr).unwrap();
     println!("Listening to {}", addr);
+    if !addr.ip().is_loopback() {
+        println!(
+            "Warning: anything that can reach {} can change the patch",
+            addr
+        );
+    }
 
-    let mut buf = [0u8; rosc::decoder::MTU];
+    let mut buf = [0u8; MAX_PACKET_SIZE];
 
     loop {
         match sock.recv_from(&mut buf) {
-            Ok((size, _addr)) => match rosc::decoder::decode(&buf[..size]) {
+            Ok((size, from)) => match rosc::decoder::decode(&buf[..size]) {
+                Ok(OscPacket::Message(ref message)) if message.addr == "/subscribe" => {
+                    if addr.ip().is_loopback() {
+                        println!("Subscribed {}", from);
+                        clients.lock().unwrap().subscribe(from, &tx);
+                    } else if let Some(nonce) = clients.lock().unwrap().challenge(from) {
+                        let challenge = OscPacket::Message(OscMessage {
+                            addr: "/subscribe-challenge".to_owned(),
+                            args: vec![OscType::String(nonce.to_string())],
+                        });
+                        if let Err(err) = sock.send_to(&encode(&challenge), from) {
+                            println!("Error sending to {}: {}", from, err);
+                        }
+                    } else {
+                        println!("Too many subscribe requests, ignored {}", from);
+                    }
+                }
+                Ok(OscPacket::Message(ref message)) if message.addr == "/subscribe-confirm" => {
+                    let mut clients = clients.lock().unwrap();
+                    match uuid_arg(message) {
+                        Some(nonce) if clients.confirm(from, nonce) => {
+                            println!("Subscribed {}", from);
+                            clients.subscribe(from, &tx);
+                        }
+                        _ => println!("Ignored an unexpected subscribe confirmation from {}", from),
+                    }
+                }
+                Ok(OscPacket::Message(ref message)) if message.addr == "/unsubscribe" => {
+                    println!("Unsubscribed {}", from);
+                    clients.lock().unwrap().unsubscribe(from, &tx);
+                }
                 Ok(packet) => {
+                    if let OscPacket::Message(ref message) = packet {
+                        let mut clients = clients.lock().unwrap();
+                        if let Some(heard) = clients.subscribers.get_mut(&from) {
+                            *heard = Instant::now();
+                        }
+                        match (message.addr.as_str(), uuid_arg(message)) {
+                            ("/create-scope", Some(id))
+                                if clients.subscribers.contains_key(&from) =>
+                            {
+                                clients.scope_owners.insert(id, from);
+                            }
+                            ("/delete-scope", Some(id)) => {
+                                clients.scope_owners.remove(&id);
+                            }
+                            _ => {}
+                        }
+                    }
                     // println!("{:?}", packet);
-                    osc_to_message(packet, &tx)
+                    for message in collect_messages(iter::once(packet)) {
+                        let message = InputMessage::FromClient(from.to_string(), Box::new(message));
+                        if let Err(e) = tx.send(message) {
+                            println!("Error passing on a message from {}: {}", from, e);
+                        }
+                    }
                 }
                 Err(err) => {
                     println!("{:?}", err);
@@ -53,16 +259,20 @@ pub fn start_recieving_server(host_address: String, tx: Sender<InputMessage>) {
 
 pub fn spawn_server(
     client_address: String,
+    bind_address: String,
     server_port: String,
     tx: Sender<InputMessage>,
     rx: Receiver<OutputMessage>,
 ) -> (JoinHandle<()>, JoinHandle<()>) {
-    let host_address = format!("127.0.0.1:{}", server_port);
+    let host_address = format!("{}:{}", bind_address, server_port);
+    let clients = SharedClients::default();
     let recieving_server_handle = {
         let host_address = host_address.clone();
-        thread::spawn(move || start_recieving_server(host_address, tx))
+        let clients = clients.clone();
+        thread::spawn(move || start_recieving_server(host_address, tx, clients))
     };
-    let sending_server_handle = thread::spawn(move || start_sending_server(client_address, rx));
+    let sending_server_handle =
+        thread::spawn(move || start_sending_server(client_address, rx, clients));
 
     (recieving_server_handle, sending_server_handle)
 }
@@ -0,0 +1,63 @@
+use modular_core::crossbeam_channel::Sender;
+use modular_core::{message::InputMessage, types::PatchGraph};
+use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::mpsc::channel,
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+pub(crate) fn read_patch_file(path: &Path) -> anyhow::Result<PatchGraph> {
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+fn send_patch_file(path: &Path, tx: &Sender<InputMessage>) {
+    match read_patch_file(path) {
+        Ok(graph) => {
+            if let Err(e) = tx.send(InputMessage::SetPatchGraph(graph)) {
+                println!("Error sending patch: {}", e);
+            }
+        }
+        Err(err) => println!("Error reading {}: {}", path.display(), err),
+    }
+}
+
+// Loads the patch file now and again every time it's saved. The directory is watched rather than
+// the file since many editors save by writing a new file and renaming it over the old one.
+pub fn watch_patch_file(path: PathBuf, tx: Sender<InputMessage>) -> JoinHandle<()> {
+    thread::spawn(move || {
+        send_patch_file(&path, &tx);
+
+        let dir = match path.parent() {
+            Some(dir) if dir != Path::new("") => dir.to_owned(),
+            _ => PathBuf::from("."),
+        };
+        let (watch_tx, watch_rx) = channel();
+        let mut watcher = match watcher(watch_tx, Duration::from_millis(200)) {
+            Ok(watcher) => watcher,
+            Err(err) => {
+                println!("Error watching {}: {}", path.display(), err);
+                return;
+            }
+        };
+        if let Err(err) = watcher.watch(&dir, RecursiveMode::NonRecursive) {
+            println!("Error watching {}: {}", path.display(), err);
+            return;
+        }
+        println!("Watching {}", path.display());
+
+        for event in watch_rx {
+            let changed = match event {
+                DebouncedEvent::Create(ref changed)
+                | DebouncedEvent::Write(ref changed)
+                | DebouncedEvent::Rename(_, ref changed) => changed,
+                _ => continue,
+            };
+            if changed.file_name() == path.file_name() {
+                send_patch_file(&path, &tx);
+            }
+        }
+    })
+}
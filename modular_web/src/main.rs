@@ -1,7 +1,7 @@
 use client::spawn_client;
 use futures::StreamExt;
 use modular_core::{crossbeam_channel::unbounded, message::InputMessage};
-use modular_server::spawn;
+use modular_server::{spawn, ServerConfig};
 use std::{
     collections::HashMap,
     sync::{
@@ -25,8 +25,8 @@ type IncomingMap = Arc<RwLock<HashMap<usize, UnboundedSender<osc::Message>>>>;
 static NEXT_USER_ID: AtomicUsize = AtomicUsize::new(1);
 #[tokio::main]
 async fn main() {
-    let (_modular_handle, _receiving_server_handle, _sending_server_handle) =
-        spawn("127.0.0.1:7813".to_owned(), "7812".to_owned());
+    let (_modular_handle, _receiving_server_handle, _sending_server_handle, _incoming_tx) =
+        spawn(ServerConfig::default());
 
     let (incoming_tx, incoming_rx) = unbounded();
     let (outgoing_tx, outgoing_rx) = unbounded();